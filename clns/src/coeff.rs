@@ -0,0 +1,663 @@
+use std::{
+    cmp::Ordering,
+    fmt::{Debug, Display},
+    hash::{Hash, Hasher},
+    ops::{
+        Add, AddAssign, Div, DivAssign, Mul, MulAssign, Neg, Shl, ShlAssign, Shr, ShrAssign, Sub,
+        SubAssign,
+    },
+};
+
+use num_bigint::{BigInt, BigUint, Sign};
+use num_integer::Integer;
+
+// matrix coefficient
+// most units spend their first few hundred terms with coefficients that fit in a machine word
+// so we keep them inline until they overflow, and only then promote to a bigint
+// every op normalizes, so Big is only ever used for values that don't fit in an i64
+// (but the variants are public, so Eq/Hash/Ord go by value rather than rely on that)
+#[derive(Clone)]
+pub enum Coeff {
+    Small(i64),
+    Big(BigInt),
+}
+
+use Coeff::{Big, Small};
+
+impl Coeff {
+    fn normalize(b: BigInt) -> Self {
+        match i64::try_from(&b) {
+            Ok(v) => Small(v),
+            Err(_) => Big(b),
+        }
+    }
+
+    // (to be called after mutating a Big in place)
+    fn demote(&mut self) {
+        if let Big(b) = self
+            && let Ok(v) = i64::try_from(&*b)
+        {
+            *self = Small(v);
+        }
+    }
+
+    pub fn to_bigint(&self) -> BigInt {
+        match self {
+            Small(a) => BigInt::from(*a),
+            Big(b) => b.clone(),
+        }
+    }
+
+    pub fn is_small(&self) -> bool {
+        matches!(self, Small(_))
+    }
+
+    pub fn sign(&self) -> Sign {
+        match self {
+            Small(a) => match a.cmp(&0) {
+                Ordering::Less => Sign::Minus,
+                Ordering::Equal => Sign::NoSign,
+                Ordering::Greater => Sign::Plus,
+            },
+            Big(b) => b.sign(),
+        }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.sign() == Sign::NoSign
+    }
+
+    // two's complement semantics, same as BigInt::bit
+    pub fn bit(&self, bit: u64) -> bool {
+        match self {
+            Small(a) if bit < 64 => (a >> bit) & 1 == 1,
+            Small(a) => *a < 0,
+            Big(b) => b.bit(bit),
+        }
+    }
+
+    pub fn bits(&self) -> u64 {
+        match self {
+            Small(a) => (64 - a.unsigned_abs().leading_zeros()) as u64,
+            Big(b) => b.bits(),
+        }
+    }
+
+    pub fn trailing_zeros(&self) -> Option<u64> {
+        match self {
+            Small(0) => None,
+            Small(a) => Some(a.trailing_zeros() as u64),
+            Big(b) => b.trailing_zeros(),
+        }
+    }
+
+    pub fn abs(&self) -> Self {
+        match self {
+            Small(a) => a
+                .checked_abs()
+                .map(Small)
+                .unwrap_or_else(|| Big(BigInt::from(*a).magnitude().clone().into())),
+            Big(b) => Big(b.magnitude().clone().into()),
+        }
+    }
+
+    // truncating division, like BigInt::checked_div
+    pub fn checked_div(&self, rhs: &Self) -> Option<Self> {
+        if rhs.is_zero() {
+            return None;
+        }
+        Some(match (self, rhs) {
+            (Small(a), Small(b)) => a
+                .checked_div(*b)
+                .map(Small)
+                .unwrap_or_else(|| Self::normalize(BigInt::from(*a) / b)),
+            _ => Self::normalize(self.to_bigint() / rhs.to_bigint()),
+        })
+    }
+
+    // always nonnegative; gcd(0, 0) = 0
+    pub fn gcd(&self, rhs: &Self) -> Self {
+        match (self, rhs) {
+            (Small(a), Small(b)) if *a != i64::MIN && *b != i64::MIN => Small(a.gcd(b)),
+            _ => Self::normalize(self.to_bigint().gcd(&rhs.to_bigint())),
+        }
+    }
+}
+
+impl Default for Coeff {
+    fn default() -> Self {
+        Small(0)
+    }
+}
+
+impl Debug for Coeff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Small(a) => write!(f, "{}", a),
+            Big(b) => write!(f, "{}", b),
+        }
+    }
+}
+
+impl Display for Coeff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(self, f)
+    }
+}
+
+// conversions
+
+macro_rules! from_prim {
+    ($($t:ty),*) => {
+        $(impl From<$t> for Coeff {
+            fn from(v: $t) -> Self {
+                match i64::try_from(v) {
+                    Ok(v) => Small(v),
+                    Err(_) => Big(v.into()),
+                }
+            }
+        })*
+    };
+}
+
+from_prim!(i32, i64, i128, u32, u64, usize);
+
+impl From<BigInt> for Coeff {
+    fn from(b: BigInt) -> Self {
+        Self::normalize(b)
+    }
+}
+
+impl From<&BigInt> for Coeff {
+    fn from(b: &BigInt) -> Self {
+        match i64::try_from(b) {
+            Ok(v) => Small(v),
+            Err(_) => Big(b.clone()),
+        }
+    }
+}
+
+impl From<BigUint> for Coeff {
+    fn from(b: BigUint) -> Self {
+        Self::normalize(b.into())
+    }
+}
+
+impl From<Coeff> for BigInt {
+    fn from(c: Coeff) -> Self {
+        match c {
+            Small(a) => a.into(),
+            Big(b) => b,
+        }
+    }
+}
+
+impl From<&Coeff> for BigInt {
+    fn from(c: &Coeff) -> Self {
+        c.to_bigint()
+    }
+}
+
+// comparison and hashing
+
+impl PartialEq for Coeff {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Coeff {}
+
+// (a value that fits hashes as an i64, however it's stored)
+impl Hash for Coeff {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match self {
+            Small(a) => a.hash(state),
+            Big(b) => match i64::try_from(b) {
+                Ok(a) => a.hash(state),
+                Err(_) => b.hash(state),
+            },
+        }
+    }
+}
+
+impl Ord for Coeff {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (Small(a), Small(b)) => a.cmp(b),
+            (Big(a), Big(b)) => a.cmp(b),
+            (Small(a), Big(b)) => BigInt::from(*a).cmp(b),
+            (Big(a), Small(b)) => a.cmp(&BigInt::from(*b)),
+        }
+    }
+}
+
+impl PartialOrd for Coeff {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// arithmetic
+// the core of each op works on references
+// everything else (owned operands, assign ops, i64 scalars) forwards to it
+
+fn add(a: &Coeff, b: &Coeff) -> Coeff {
+    match (a, b) {
+        (Small(x), Small(y)) => x
+            .checked_add(*y)
+            .map(Small)
+            .unwrap_or_else(|| Big(BigInt::from(*x) + y)),
+        (Big(x), Small(y)) | (Small(y), Big(x)) => Coeff::normalize(x + y),
+        (Big(x), Big(y)) => Coeff::normalize(x + y),
+    }
+}
+
+fn sub(a: &Coeff, b: &Coeff) -> Coeff {
+    match (a, b) {
+        (Small(x), Small(y)) => x
+            .checked_sub(*y)
+            .map(Small)
+            .unwrap_or_else(|| Big(BigInt::from(*x) - y)),
+        (Big(x), Small(y)) => Coeff::normalize(x - y),
+        (Small(x), Big(y)) => Coeff::normalize(x - y),
+        (Big(x), Big(y)) => Coeff::normalize(x - y),
+    }
+}
+
+fn mul(a: &Coeff, b: &Coeff) -> Coeff {
+    match (a, b) {
+        (Small(x), Small(y)) => x
+            .checked_mul(*y)
+            .map(Small)
+            .unwrap_or_else(|| Big(BigInt::from(*x) * y)),
+        (Big(x), Small(y)) | (Small(y), Big(x)) => Coeff::normalize(x * y),
+        (Big(x), Big(y)) => Coeff::normalize(x * y),
+    }
+}
+
+fn div(a: &Coeff, b: &Coeff) -> Coeff {
+    a.checked_div(b).expect("attempt to divide by zero")
+}
+
+fn neg(a: &Coeff) -> Coeff {
+    match a {
+        Small(x) => x
+            .checked_neg()
+            .map(Small)
+            .unwrap_or_else(|| Big(-BigInt::from(*x))),
+        Big(x) => Coeff::normalize(-x),
+    }
+}
+
+fn shl(a: &Coeff, n: u32) -> Coeff {
+    match a {
+        Small(0) => Small(0),
+        // fits if we don't shift any significant bits into (or past) the sign bit
+        Small(x) if n < 64 && (x << n) >> n == *x => Small(x << n),
+        Small(x) => Big(BigInt::from(*x) << n),
+        Big(x) => Big(x << n),
+    }
+}
+
+// rounds towards -oo, same as BigInt
+fn shr(a: &Coeff, n: u32) -> Coeff {
+    match a {
+        Small(x) => Small(x >> n.min(63)),
+        Big(x) => Coeff::normalize(x >> n),
+    }
+}
+
+// the in-place versions avoid reallocating once we're big
+macro_rules! assign_core {
+    ($name:ident, $core:ident, $op:tt) => {
+        fn $name(a: &mut Coeff, b: &Coeff) {
+            if let Big(x) = a {
+                match b {
+                    Small(y) => *x $op *y,
+                    Big(y) => *x $op y,
+                }
+                a.demote();
+            } else {
+                *a = $core(a, b);
+            }
+        }
+    };
+}
+
+assign_core!(add_assign, add, +=);
+assign_core!(sub_assign, sub, -=);
+assign_core!(mul_assign, mul, *=);
+
+fn div_assign(a: &mut Coeff, b: &Coeff) {
+    *a = div(a, b);
+}
+
+macro_rules! binop {
+    ($tr:ident, $f:ident, $core:ident, $atr:ident, $af:ident) => {
+        impl $tr<&Coeff> for &Coeff {
+            type Output = Coeff;
+            fn $f(self, rhs: &Coeff) -> Coeff {
+                $core(self, rhs)
+            }
+        }
+        impl $tr<Coeff> for &Coeff {
+            type Output = Coeff;
+            fn $f(self, rhs: Coeff) -> Coeff {
+                $core(self, &rhs)
+            }
+        }
+        impl $tr<&Coeff> for Coeff {
+            type Output = Coeff;
+            fn $f(self, rhs: &Coeff) -> Coeff {
+                $core(&self, rhs)
+            }
+        }
+        impl $tr<Coeff> for Coeff {
+            type Output = Coeff;
+            fn $f(self, rhs: Coeff) -> Coeff {
+                $core(&self, &rhs)
+            }
+        }
+        impl $tr<i64> for &Coeff {
+            type Output = Coeff;
+            fn $f(self, rhs: i64) -> Coeff {
+                $core(self, &Small(rhs))
+            }
+        }
+        impl $tr<i64> for Coeff {
+            type Output = Coeff;
+            fn $f(self, rhs: i64) -> Coeff {
+                $core(&self, &Small(rhs))
+            }
+        }
+        impl $tr<&Coeff> for i64 {
+            type Output = Coeff;
+            fn $f(self, rhs: &Coeff) -> Coeff {
+                $core(&Small(self), rhs)
+            }
+        }
+        impl $tr<Coeff> for i64 {
+            type Output = Coeff;
+            fn $f(self, rhs: Coeff) -> Coeff {
+                $core(&Small(self), &rhs)
+            }
+        }
+        impl $atr<&Coeff> for Coeff {
+            fn $af(&mut self, rhs: &Coeff) {
+                $af(self, rhs);
+            }
+        }
+        impl $atr<Coeff> for Coeff {
+            fn $af(&mut self, rhs: Coeff) {
+                $af(self, &rhs);
+            }
+        }
+        impl $atr<i64> for Coeff {
+            fn $af(&mut self, rhs: i64) {
+                $af(self, &Small(rhs));
+            }
+        }
+    };
+}
+
+binop!(Add, add, add, AddAssign, add_assign);
+binop!(Sub, sub, sub, SubAssign, sub_assign);
+binop!(Mul, mul, mul, MulAssign, mul_assign);
+binop!(Div, div, div, DivAssign, div_assign);
+
+impl Neg for Coeff {
+    type Output = Coeff;
+    fn neg(self) -> Coeff {
+        neg(&self)
+    }
+}
+
+impl Neg for &Coeff {
+    type Output = Coeff;
+    fn neg(self) -> Coeff {
+        neg(self)
+    }
+}
+
+impl Shl<u32> for Coeff {
+    type Output = Coeff;
+    fn shl(self, n: u32) -> Coeff {
+        shl(&self, n)
+    }
+}
+
+impl Shl<u32> for &Coeff {
+    type Output = Coeff;
+    fn shl(self, n: u32) -> Coeff {
+        shl(self, n)
+    }
+}
+
+impl Shr<u32> for Coeff {
+    type Output = Coeff;
+    fn shr(self, n: u32) -> Coeff {
+        shr(&self, n)
+    }
+}
+
+impl Shr<u32> for &Coeff {
+    type Output = Coeff;
+    fn shr(self, n: u32) -> Coeff {
+        shr(self, n)
+    }
+}
+
+impl ShlAssign<u32> for Coeff {
+    fn shl_assign(&mut self, n: u32) {
+        match self {
+            Big(b) => *b <<= n,
+            _ => *self = shl(self, n),
+        }
+    }
+}
+
+impl ShrAssign<u32> for Coeff {
+    fn shr_assign(&mut self, n: u32) {
+        match self {
+            Big(b) => {
+                *b >>= n;
+                self.demote();
+            }
+            _ => *self = shr(self, n),
+        }
+    }
+}
+
+impl std::iter::Sum for Coeff {
+    fn sum<I: Iterator<Item = Coeff>>(iter: I) -> Coeff {
+        iter.fold(Small(0), |acc, i| acc + i)
+    }
+}
+
+impl<'a> std::iter::Sum<&'a Coeff> for Coeff {
+    fn sum<I: Iterator<Item = &'a Coeff>>(iter: I) -> Coeff {
+        iter.fold(Small(0), |acc, i| acc + i)
+    }
+}
+
+// mat[dst] += mat[src] and mat[dst] -= mat[src]
+// without having to clone mat[src] to get around the borrow checker
+pub(crate) fn add_idx(mat: &mut [Coeff], dst: usize, src: usize) {
+    let (d, s) = pair_mut(mat, dst, src);
+    add_assign(d, s);
+}
+
+pub(crate) fn sub_idx(mat: &mut [Coeff], dst: usize, src: usize) {
+    let (d, s) = pair_mut(mat, dst, src);
+    sub_assign(d, s);
+}
+
+fn pair_mut(mat: &mut [Coeff], dst: usize, src: usize) -> (&mut Coeff, &Coeff) {
+    assert_ne!(dst, src);
+    if dst < src {
+        let (l, r) = mat.split_at_mut(src);
+        (&mut l[dst], &r[0])
+    } else {
+        let (l, r) = mat.split_at_mut(dst);
+        (&mut r[0], &l[src])
+    }
+}
+
+// (always goes through BigInt, so that deserializing can't break the Small/Big invariant)
+#[cfg(feature = "serde")]
+impl serde::Serialize for Coeff {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.to_bigint().serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Coeff {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        BigInt::deserialize(deserializer).map(Self::normalize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // values either side of the i64 boundary (and a few that are well clear of it)
+    fn boundaries() -> Vec<BigInt> {
+        let min = BigInt::from(i64::MIN);
+        let max = BigInt::from(i64::MAX);
+        vec![
+            0.into(),
+            1.into(),
+            (-1).into(),
+            7.into(),
+            (-12).into(),
+            (1i64 << 32).into(),
+            max.clone(),
+            &max - 1,
+            &max + 1,
+            min.clone(),
+            &min + 1,
+            &min - 1,
+            -&min,
+            &max * &max,
+            -(&max * 3u32),
+        ]
+    }
+
+    // the same value, and Small iff it fits (so we stay on the fast paths)
+    fn check(c: Coeff, b: BigInt) {
+        assert_eq!(
+            c.is_small(),
+            i64::try_from(&b).is_ok(),
+            "{} normalized wrong",
+            b
+        );
+        assert_eq!(c.to_bigint(), b);
+    }
+
+    #[test]
+    fn neg_of_min_promotes() {
+        let min = Coeff::from(i64::MIN);
+        check(-&min, -BigInt::from(i64::MIN));
+        check(min.abs(), -BigInt::from(i64::MIN));
+        // and comes back down again
+        check(-(-&min), BigInt::from(i64::MIN));
+        check(
+            min.checked_div(&Coeff::from(-1)).unwrap(),
+            -BigInt::from(i64::MIN),
+        );
+    }
+
+    #[test]
+    fn ops_match_bigint() {
+        for a in boundaries() {
+            for b in boundaries() {
+                let (ca, cb) = (Coeff::from(&a), Coeff::from(&b));
+                check(&ca + &cb, &a + &b);
+                check(&ca - &cb, &a - &b);
+                check(&ca * &cb, &a * &b);
+                let mut assigned = ca.clone();
+                assigned *= &cb;
+                check(assigned, &a * &b);
+                let mut assigned = ca.clone();
+                assigned -= &cb;
+                check(assigned, &a - &b);
+                if b != BigInt::from(0) {
+                    check(ca.checked_div(&cb).unwrap(), &a / &b);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn shifts_match_bigint() {
+        for a in boundaries() {
+            for n in [0, 1, 31, 62, 63, 64, 100] {
+                let ca = Coeff::from(&a);
+                check(&ca << n, &a << n);
+                check(&ca >> n, &a >> n);
+                let mut assigned = ca.clone();
+                assigned <<= n;
+                check(assigned, &a << n);
+                let mut assigned = ca;
+                assigned >>= n;
+                check(assigned, &a >> n);
+            }
+        }
+        // the last bit that fits, and one past it
+        check(Coeff::from(1) << 62, BigInt::from(1) << 62);
+        check(Coeff::from(1) << 63, BigInt::from(1) << 63);
+        check(Coeff::from(-1) << 63, BigInt::from(i64::MIN));
+    }
+
+    #[test]
+    fn gcd_matches_bigint() {
+        for a in boundaries() {
+            for b in boundaries() {
+                check(Coeff::from(&a).gcd(&Coeff::from(&b)), a.gcd(&b));
+            }
+        }
+        check(
+            Coeff::from(i64::MIN).gcd(&Coeff::from(0)),
+            -BigInt::from(i64::MIN),
+        );
+    }
+
+    #[test]
+    fn cmp_matches_bigint() {
+        for a in boundaries() {
+            for b in boundaries() {
+                assert_eq!(
+                    Coeff::from(&a).cmp(&Coeff::from(&b)),
+                    a.cmp(&b),
+                    "{} vs {}",
+                    a,
+                    b
+                );
+            }
+        }
+    }
+
+    // (nothing stops a caller from building an unnormalized Big themselves)
+    #[test]
+    fn unnormalized_big_is_the_same_value() {
+        use std::collections::hash_map::DefaultHasher;
+        let hash = |c: &Coeff| {
+            let mut hasher = DefaultHasher::new();
+            c.hash(&mut hasher);
+            hasher.finish()
+        };
+        for a in boundaries() {
+            let big = Big(a.clone());
+            let normal = Coeff::from(&a);
+            assert_eq!(big, normal);
+            assert_eq!(hash(&big), hash(&normal));
+            assert_eq!(big.is_zero(), a.sign() == Sign::NoSign);
+            for b in boundaries() {
+                assert_eq!(big.cmp(&Coeff::from(&b)), a.cmp(&b), "{} vs {}", a, b);
+            }
+        }
+    }
+}
@@ -0,0 +1,46 @@
+use std::fmt::Display;
+
+use crate::workgroup::{UnitId, ValidationError};
+
+// everything that can go wrong when building or running workgroups
+// (as opposed to values being undefined, which is just another term)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    // the id came from another workgroup, or its unit has since been removed
+    UnknownUnit(UnitId),
+    // the graph didn't pass validation (see Workgroup::validate)
+    InvalidGraph(Vec<ValidationError>),
+    // the unit isn't the kind this operation works on
+    WrongKind {
+        unit: UnitId,
+        expected: &'static str,
+        found: &'static str,
+    },
+    // not enough input has come through to answer yet (try again after more cycles)
+    Undecided,
+}
+
+impl From<Vec<ValidationError>> for Error {
+    fn from(errors: Vec<ValidationError>) -> Self {
+        Self::InvalidGraph(errors)
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnknownUnit(id) => write!(f, "{:?} isn't in this workgroup", id),
+            Self::InvalidGraph(errors) => write!(f, "invalid workgroup graph: {:?}", errors),
+            Self::WrongKind {
+                unit,
+                expected,
+                found,
+            } => write!(f, "{:?} is a {}, not a {}", unit, found, expected),
+            Self::Undecided => write!(f, "not enough input to decide yet"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+pub type Result<T> = std::result::Result<T, Error>;
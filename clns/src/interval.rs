@@ -0,0 +1,324 @@
+use std::{cmp::Ordering, fmt::Display};
+
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use num_traits::{ToPrimitive, Zero};
+
+// one end of an interval
+// infinite ends are always open (we never say the value is exactly oo here, see Lft::interval)
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Bound {
+    NegInf,
+    Closed(BigRational),
+    Open(BigRational),
+    PosInf,
+}
+
+// a range of rationals, lo <= hi
+// endpoints are always fully reduced (BigRational does that for us)
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Interval {
+    pub lo: Bound,
+    pub hi: Bound,
+}
+
+impl Bound {
+    pub fn rational(&self) -> Option<&BigRational> {
+        match self {
+            Self::Closed(q) | Self::Open(q) => Some(q),
+            Self::NegInf | Self::PosInf => None,
+        }
+    }
+
+    pub fn is_closed(&self) -> bool {
+        matches!(self, Self::Closed(_))
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        match self {
+            Self::NegInf => f64::NEG_INFINITY,
+            Self::PosInf => f64::INFINITY,
+            Self::Closed(q) | Self::Open(q) => q.to_f64().unwrap_or(f64::NAN),
+        }
+    }
+
+    // where the bound sits relative to q, ignoring whether it's open
+    pub fn cmp_rational(&self, q: &BigRational) -> Ordering {
+        match self {
+            Self::NegInf => Ordering::Less,
+            Self::PosInf => Ordering::Greater,
+            Self::Closed(p) | Self::Open(p) => p.cmp(q),
+        }
+    }
+
+    // same, against another bound
+    fn cmp_point(&self, other: &Bound) -> Ordering {
+        match other.rational() {
+            Some(q) => self.cmp_rational(q),
+            None => match (self, other) {
+                (Self::NegInf, Self::NegInf) | (Self::PosInf, Self::PosInf) => Ordering::Equal,
+                (_, Self::NegInf) => Ordering::Greater,
+                _ => Ordering::Less,
+            },
+        }
+    }
+}
+
+impl Interval {
+    // takes the bounds in either order
+    pub fn new(a: Bound, b: Bound) -> Self {
+        if a.cmp_point(&b) == Ordering::Greater {
+            Self { lo: b, hi: a }
+        } else {
+            Self { lo: a, hi: b }
+        }
+    }
+
+    pub fn closed(a: BigRational, b: BigRational) -> Self {
+        Self::new(Bound::Closed(a), Bound::Closed(b))
+    }
+
+    pub fn point(q: BigRational) -> Self {
+        Self::closed(q.clone(), q)
+    }
+
+    pub fn everything() -> Self {
+        Self {
+            lo: Bound::NegInf,
+            hi: Bound::PosInf,
+        }
+    }
+
+    // None if either end is infinite
+    pub fn width(&self) -> Option<BigRational> {
+        Some(self.hi.rational()? - self.lo.rational()?)
+    }
+
+    // None if either end is infinite
+    pub fn midpoint(&self) -> Option<BigRational> {
+        Some((self.lo.rational()? + self.hi.rational()?) / BigInt::from(2))
+    }
+
+    pub fn contains(&self, q: &BigRational) -> bool {
+        let above_lo = match self.lo.cmp_rational(q) {
+            Ordering::Less => true,
+            Ordering::Equal => self.lo.is_closed(),
+            Ordering::Greater => false,
+        };
+        let below_hi = match self.hi.cmp_rational(q) {
+            Ordering::Greater => true,
+            Ordering::Equal => self.hi.is_closed(),
+            Ordering::Less => false,
+        };
+        above_lo && below_hi
+    }
+
+    pub fn intersects(&self, other: &Interval) -> bool {
+        self.intersection(other).is_some()
+    }
+
+    // None if they don't meet
+    pub fn intersection(&self, other: &Interval) -> Option<Interval> {
+        // the overlap (if any) runs from the higher lo to the lower hi
+        let lo = match self.lo.cmp_point(&other.lo) {
+            Ordering::Less => &other.lo,
+            Ordering::Greater => &self.lo,
+            // (an open end wins a tie)
+            Ordering::Equal if self.lo.is_closed() => &other.lo,
+            Ordering::Equal => &self.lo,
+        };
+        let hi = match self.hi.cmp_point(&other.hi) {
+            Ordering::Less => &self.hi,
+            Ordering::Greater => &other.hi,
+            Ordering::Equal if self.hi.is_closed() => &other.hi,
+            Ordering::Equal => &self.hi,
+        };
+        let meet = match lo.cmp_point(hi) {
+            Ordering::Less => true,
+            Ordering::Equal => lo.is_closed() && hi.is_closed(),
+            Ordering::Greater => false,
+        };
+        meet.then(|| Interval {
+            lo: lo.clone(),
+            hi: hi.clone(),
+        })
+    }
+
+    // the rational in here with the smallest denominator (and then numerator)
+    // (open ends are treated as closed)
+    pub fn simplest(&self) -> Option<BigRational> {
+        let (lo, hi) = (self.lo.rational()?, self.hi.rational()?);
+        let zero = BigRational::zero();
+        Some(if *lo <= zero && zero <= *hi {
+            zero
+        } else if *hi < zero {
+            -simplest_positive(&-hi, &-lo)
+        } else {
+            simplest_positive(lo, hi)
+        })
+    }
+
+    pub fn is_bounded(&self) -> bool {
+        self.lo.rational().is_some() && self.hi.rational().is_some()
+    }
+
+    pub fn to_f64(&self) -> (f64, f64) {
+        (self.lo.to_f64(), self.hi.to_f64())
+    }
+}
+
+// 0 < lo <= hi
+// walks down the continued fractions of both ends until they part ways
+// (the first integer between them, at that depth, is the answer)
+// on plain integers rather than rationals, since lo/hi can be thousands of bits
+fn simplest_positive(lo: &BigRational, hi: &BigRational) -> BigRational {
+    let (mut a, mut b) = (lo.numer().clone(), lo.denom().clone());
+    let (mut c, mut d) = (hi.numer().clone(), hi.denom().clone());
+    // the convergent so far: the answer is (p x + pp) / (q x + qq) for the x we end up with
+    let (mut p, mut pp) = (BigInt::from(1), BigInt::from(0));
+    let (mut q, mut qq) = (BigInt::from(0), BigInt::from(1));
+    loop {
+        // lo = a/b, hi = c/d
+        let ceil = (&a + &b - 1u32) / &b;
+        if &ceil * &d <= c {
+            return BigRational::new(&p * &ceil + pp, &q * &ceil + qq);
+        }
+        // both in (n, n+1), so go down a level: x -> n + 1/x flips the ends
+        let n = ceil - 1u32;
+        (a, b, c, d) = (d.clone(), &c - &n * &d, b.clone(), &a - &n * &b);
+        (p, pp) = (&p * &n + &pp, p);
+        (q, qq) = (&q * &n + &qq, q);
+    }
+}
+
+impl From<BigRational> for Interval {
+    fn from(q: BigRational) -> Self {
+        Self::point(q)
+    }
+}
+
+// [1/2, oo) and the like
+impl Display for Interval {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.lo {
+            Bound::NegInf => write!(f, "(-oo")?,
+            Bound::Closed(q) => write!(f, "[{}", q)?,
+            Bound::Open(q) => write!(f, "({}", q)?,
+            Bound::PosInf => write!(f, "(oo")?,
+        }
+        match &self.hi {
+            Bound::NegInf => write!(f, ", -oo)"),
+            Bound::Closed(q) => write!(f, ", {}]", q),
+            Bound::Open(q) => write!(f, ", {})", q),
+            Bound::PosInf => write!(f, ", oo)"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn q(n: i64, d: i64) -> BigRational {
+        BigRational::new(n.into(), d.into())
+    }
+
+    #[test]
+    fn new_orders_its_bounds() {
+        let interval = Interval::closed(q(3, 1), q(-1, 2));
+        assert_eq!(interval.lo, Bound::Closed(q(-1, 2)));
+        assert_eq!(interval.hi, Bound::Closed(q(3, 1)));
+        let interval = Interval::new(Bound::PosInf, Bound::Open(q(1, 1)));
+        assert_eq!(interval.lo, Bound::Open(q(1, 1)));
+        assert_eq!(interval.hi, Bound::PosInf);
+        let interval = Interval::new(Bound::Closed(q(0, 1)), Bound::NegInf);
+        assert_eq!(interval.lo, Bound::NegInf);
+    }
+
+    #[test]
+    fn endpoints_are_reduced() {
+        let interval = Interval::closed(q(6, 4), q(-10, -20));
+        assert_eq!(interval, Interval::closed(q(1, 2), q(3, 2)));
+        assert_eq!(interval.to_string(), "[1/2, 3/2]");
+    }
+
+    #[test]
+    fn open_and_infinite_ends() {
+        let half_open = Interval::new(Bound::Open(q(1, 1)), Bound::Closed(q(2, 1)));
+        assert!(!half_open.contains(&q(1, 1)));
+        assert!(half_open.contains(&q(2, 1)));
+        assert_eq!(half_open.to_string(), "(1, 2]");
+        let above = Interval::new(Bound::Closed(q(1, 1)), Bound::PosInf);
+        assert!(above.contains(&q(1, 1)));
+        assert!(above.contains(&q(1 << 40, 1)));
+        assert!(!above.contains(&q(-1, 1)));
+        assert!(!above.is_bounded());
+        assert_eq!(above.to_string(), "[1, oo)");
+        assert_eq!(above.to_f64(), (1.0, f64::INFINITY));
+        assert!(Interval::everything().contains(&q(-7, 3)));
+    }
+
+    #[test]
+    fn width_and_midpoint() {
+        let interval = Interval::closed(q(1, 3), q(1, 2));
+        assert_eq!(interval.width(), Some(q(1, 6)));
+        assert_eq!(interval.midpoint(), Some(q(5, 12)));
+        assert_eq!(Interval::point(q(2, 1)).width(), Some(q(0, 1)));
+        // (nothing to measure once an end's infinite)
+        let above = Interval::new(Bound::Open(q(1, 1)), Bound::PosInf);
+        assert_eq!(above.width(), None);
+        assert_eq!(above.midpoint(), None);
+        assert_eq!(Interval::everything().width(), None);
+    }
+
+    #[test]
+    fn intersections() {
+        let unit = Interval::closed(q(0, 1), q(1, 1));
+        let touching = Interval::closed(q(1, 1), q(2, 1));
+        assert!(unit.intersects(&touching));
+        assert!(touching.intersects(&unit));
+        // (an open end at the shared point means they don't actually meet)
+        let open = Interval::new(Bound::Open(q(1, 1)), Bound::Closed(q(2, 1)));
+        assert!(!unit.intersects(&open));
+        assert!(!open.intersects(&unit));
+        let apart = Interval::closed(q(3, 1), q(4, 1));
+        assert!(!unit.intersects(&apart));
+        let inside = Interval::closed(q(1, 4), q(1, 2));
+        assert!(unit.intersects(&inside));
+        let above = Interval::new(Bound::Closed(q(1, 2)), Bound::PosInf);
+        let below = Interval::new(Bound::NegInf, Bound::Open(q(1, 2)));
+        assert!(above.intersects(&unit));
+        assert!(below.intersects(&unit));
+        assert!(!above.intersects(&below));
+        assert!(Interval::everything().intersects(&above));
+        assert!(Interval::everything().intersects(&Interval::everything()));
+        assert_eq!(unit.intersection(&touching), Some(Interval::point(q(1, 1))));
+        assert_eq!(unit.intersection(&apart), None);
+        assert_eq!(
+            above.intersection(&unit),
+            Some(Interval::closed(q(1, 2), q(1, 1)))
+        );
+        assert_eq!(
+            below.intersection(&unit),
+            Some(Interval::new(Bound::Closed(q(0, 1)), Bound::Open(q(1, 2))))
+        );
+    }
+
+    #[test]
+    fn simplest() {
+        let interval = Interval::closed(q(3, 10), q(2, 5));
+        assert_eq!(interval.simplest(), Some(q(1, 3)));
+        assert_eq!(
+            Interval::closed(q(-5, 2), q(1, 1)).simplest(),
+            Some(q(0, 1))
+        );
+        assert_eq!(
+            Interval::closed(q(-22, 7), q(-3, 1)).simplest(),
+            Some(q(-3, 1))
+        );
+        let above = Interval::new(Bound::Closed(q(1, 1)), Bound::PosInf);
+        assert_eq!(above.simplest(), None);
+    }
+}
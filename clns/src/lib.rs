@@ -1,14 +1,48 @@
 use std::fmt::Debug;
 
+// trace points go to the log crate with the "log" feature, and nowhere otherwise
+// (a library has no business printing to stdout)
+// debug is for things that happen once in a while (new layers, settling on a value)
+// trace is for things that can happen every cycle (speculation, undefined results)
+#[cfg(feature = "log")]
+macro_rules! debug {
+    ($($arg:tt)*) => { log::debug!($($arg)*) };
+}
+#[cfg(feature = "log")]
+macro_rules! trace {
+    ($($arg:tt)*) => { log::trace!($($arg)*) };
+}
+// (still type-checks the arguments, so that turning the feature on doesn't break anything)
+#[cfg(not(feature = "log"))]
+macro_rules! debug {
+    ($($arg:tt)*) => {{
+        let _ = format_args!($($arg)*);
+    }};
+}
+#[cfg(not(feature = "log"))]
+macro_rules! trace {
+    ($($arg:tt)*) => {{
+        let _ = format_args!($($arg)*);
+    }};
+}
+
+pub mod coeff;
+pub mod error;
+pub mod interval;
+pub mod reduce;
+pub mod terms;
 pub mod unit;
 pub mod workgroup;
 
+pub use error::Error;
+
 // basic idea:
 // - (use bigints)
 // - Unit (arith, sqrt, compare, cfrac/rat/float conversions; they ingest and post their egestions)
 // - Workgroup (dynamically extensible/composable DAG; exp, log, etc)
 // stuff that only has 2 stages we meter to 3
 #[derive(Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Term {
     #[default]
     Empty,
@@ -22,15 +56,13 @@ pub enum Term {
 
 impl Debug for Term {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Self::Empty => write!(f, "ø"),
-            Self::Ord => write!(f, "1"),
-            Self::DRec => write!(f, "0"),
-            Self::Rec => write!(f, "/"),
-            Self::Neg => write!(f, "-"),
-            Self::Inf => write!(f, "∞"),
-            Self::Undefined => write!(f, "!"),
-        }
+        write!(f, "{}", self.to_char())
+    }
+}
+
+impl std::fmt::Display for Term {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.to_char())
     }
 }
 
@@ -38,14 +70,11 @@ impl Debug for Term {
 // flips the signs of both of them if the denominator is negative
 // that way, we're guaranteed to have one of +/+, -/+, 0/+, or whatever/0
 // we have -oo but no -0
-pub(crate) fn rationalize(
-    num: &mut num_bigint::BigInt,
-    den: &mut num_bigint::BigInt,
-) -> num_bigint::Sign {
+pub(crate) fn rationalize(num: &mut coeff::Coeff, den: &mut coeff::Coeff) -> num_bigint::Sign {
     let (n, d) = (num.sign(), den.sign());
     if d == num_bigint::Sign::Minus {
-        *num *= -1;
-        *den *= -1;
+        *num = -&*num;
+        *den = -&*den;
     }
     if n == num_bigint::Sign::NoSign {
         num_bigint::Sign::NoSign
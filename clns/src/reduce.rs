@@ -0,0 +1,193 @@
+use crate::coeff::Coeff;
+
+// unit matrices only ever get scaled, never simplified
+// ingesting/egesting 1s strips powers of two when it can, but that's it
+// so for things like generalized cfracs (pi!) the common factor of the matrix
+// grows and grows until like half the bits are redundant
+// a gcd every cycle is too expensive (it's linear in the number of bits, and there are 4-8 of them)
+// so we amortize: only reduce every so often, or once the coefficients have grown enough
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ReducePolicy {
+    // reduce at least once every this many ticks (0 turns this off)
+    // units tick on every ingest and every egest, whatever the term
+    // (so an arith taking x and y ticks three times a cycle, and an lft that only ingests still ticks)
+    pub every: u32,
+    // reduce whenever the biggest coefficient has grown by this many bits
+    // since the last reduction (0 turns this off)
+    pub growth_bits: u64,
+}
+
+impl ReducePolicy {
+    pub const NEVER: Self = Self {
+        every: 0,
+        growth_bits: 0,
+    };
+}
+
+impl Default for ReducePolicy {
+    fn default() -> Self {
+        Self {
+            every: 64,
+            growth_bits: 256,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ReduceStats {
+    // how many gcds we computed
+    pub attempts: u64,
+    // how many of those actually found a common factor
+    pub hits: u64,
+    // total bits shaved off the biggest coefficient
+    pub bits_removed: u64,
+    // biggest coefficient we've ever seen (in bits)
+    pub peak_bits: u64,
+}
+
+impl ReduceStats {
+    // for adding up the stats of several reducers (peak_bits is the max instead)
+    pub fn merge(&mut self, other: &ReduceStats) {
+        self.attempts += other.attempts;
+        self.hits += other.hits;
+        self.bits_removed += other.bits_removed;
+        self.peak_bits = self.peak_bits.max(other.peak_bits);
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Reducer {
+    pub policy: ReducePolicy,
+    pub stats: ReduceStats,
+    ticks_since: u32,
+    bits_at_last: u64,
+}
+
+impl Reducer {
+    pub fn with_policy(policy: ReducePolicy) -> Self {
+        Self {
+            policy,
+            ..Default::default()
+        }
+    }
+
+    // call on every ingest and every egest (see ReducePolicy::every)
+    // reduces mat in place if the policy says it's time
+    pub fn tick(&mut self, mat: &mut [Coeff]) {
+        self.ticks_since += 1;
+        let bits = max_bits(mat);
+        self.stats.peak_bits = self.stats.peak_bits.max(bits);
+        let timer = self.policy.every != 0 && self.ticks_since >= self.policy.every;
+        let growth = self.policy.growth_bits != 0
+            && bits >= self.bits_at_last.saturating_add(self.policy.growth_bits);
+        if timer || growth {
+            self.reduce(mat);
+        }
+    }
+
+    // reduces mat in place right now, regardless of the policy
+    pub fn reduce(&mut self, mat: &mut [Coeff]) {
+        let before = max_bits(mat);
+        self.stats.attempts += 1;
+        if reduce_content(mat) {
+            self.stats.hits += 1;
+        }
+        let after = max_bits(mat);
+        self.stats.bits_removed += before - after;
+        self.ticks_since = 0;
+        self.bits_at_last = after;
+    }
+}
+
+pub fn max_bits(mat: &[Coeff]) -> u64 {
+    mat.iter().map(|i| i.bits()).max().unwrap_or(0)
+}
+
+// divides everything by the gcd of the whole matrix
+// returns whether the gcd was bigger than 1
+// (an all-zero matrix is left alone, it's undefined either way)
+pub fn reduce_content(mat: &mut [Coeff]) -> bool {
+    let mut g = Coeff::from(0);
+    for i in mat.iter() {
+        g = g.gcd(i);
+        // most of the time the gcd is 1 and we can bail early
+        if g == 1.into() {
+            return false;
+        }
+    }
+    if g == 0.into() {
+        return false;
+    }
+    for i in mat.iter_mut() {
+        *i /= &g;
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        Term,
+        unit::{Unit, arith::Arith, cfrac::consts, lft::Lft},
+    };
+
+    #[test]
+    fn reduce_content_divides_out_the_gcd() {
+        let mut mat = [6, -4, 10, 0].map(Coeff::from);
+        assert!(reduce_content(&mut mat));
+        assert_eq!(mat, [3, -2, 5, 0].map(Coeff::from));
+        assert!(!reduce_content(&mut mat));
+        let mut zero = [0, 0, 0, 0].map(Coeff::from);
+        assert!(!reduce_content(&mut zero));
+    }
+
+    // pi's generalized cfrac is the worst case for redundant factors
+    // (without reduction this gets to ~13k bits over the same terms)
+    #[test]
+    fn pi_stays_under_a_ceiling() {
+        let mut pi = consts::pi();
+        let mut real = 0;
+        let mut peak = 0;
+        while real < 4000 {
+            if pi.egest_z() != Term::Empty {
+                real += 1;
+            }
+            peak = peak.max(pi.max_bits());
+        }
+        assert!(peak < 4096, "pi got to {} bits", peak);
+        let stats = pi.reduce_stats();
+        assert!(stats.hits > 0);
+        assert!(stats.bits_removed > 0);
+        // (the reducer sees the matrix mid-egest, before it's been reduced)
+        assert!(stats.peak_bits < 4096);
+    }
+
+    // every ingest and egest is a tick, whichever unit it is
+    #[test]
+    fn every_counts_ingests_and_egests() {
+        let policy = ReducePolicy {
+            every: 6,
+            growth_bits: 0,
+        };
+        let mut lft = Lft::identity();
+        lft.reducer = Reducer::with_policy(policy);
+        let mut arith = Arith::add();
+        arith.reducer = Reducer::with_policy(policy);
+        // (6 ticks each: 3 ingests and 3 egests, and 2 cycles of x, y and z)
+        for _ in 0..3 {
+            lft.ingest_x(Term::Empty);
+            lft.egest_z();
+        }
+        for _ in 0..2 {
+            arith.ingest_x(Term::Empty);
+            arith.ingest_y(Term::Empty);
+            arith.egest_z();
+        }
+        assert_eq!(lft.reduce_stats().attempts, 1);
+        assert_eq!(arith.reduce_stats().attempts, 1);
+    }
+}
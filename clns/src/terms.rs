@@ -0,0 +1,260 @@
+use std::{fmt::Display, str::FromStr};
+
+use crate::Term;
+
+// a finite run of clog terms
+// e.g. a computed expansion of some constant that we want to store or ship somewhere
+// text form is the same as Term's Debug (1 0 / - ∞ ø !), with no separators
+// binary form is a little-endian u64 term count followed by the terms packed at 3 bits each
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Terms(pub Vec<Term>);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ParseTermsError {
+    InvalidChar(char),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DecodeTermsError {
+    // not even enough bytes for the header, or fewer terms than the header promised
+    TooShort,
+    // 7 isn't assigned to any term
+    InvalidCode(u8),
+    // the header's term count doesn't fit in a usize (only on 32-bit and smaller)
+    TooLong(u64),
+}
+
+impl Display for ParseTermsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidChar(c) => write!(f, "{:?} isn't a term", c),
+        }
+    }
+}
+
+impl std::error::Error for ParseTermsError {}
+
+impl Display for DecodeTermsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::TooShort => write!(f, "ran out of bytes before the last term"),
+            Self::InvalidCode(code) => write!(f, "{} isn't a term code", code),
+            Self::TooLong(len) => write!(f, "{} terms is too many to decode here", len),
+        }
+    }
+}
+
+impl std::error::Error for DecodeTermsError {}
+
+impl Term {
+    pub fn to_char(self) -> char {
+        match self {
+            Self::Empty => 'ø',
+            Self::Ord => '1',
+            Self::DRec => '0',
+            Self::Rec => '/',
+            Self::Neg => '-',
+            Self::Inf => '∞',
+            Self::Undefined => '!',
+        }
+    }
+
+    pub fn from_char(c: char) -> Option<Self> {
+        Some(match c {
+            'ø' => Self::Empty,
+            '1' => Self::Ord,
+            '0' => Self::DRec,
+            '/' => Self::Rec,
+            '-' => Self::Neg,
+            '∞' => Self::Inf,
+            '!' => Self::Undefined,
+            _ => return None,
+        })
+    }
+
+    pub fn to_code(self) -> u8 {
+        match self {
+            Self::Empty => 0,
+            Self::Ord => 1,
+            Self::DRec => 2,
+            Self::Rec => 3,
+            Self::Neg => 4,
+            Self::Inf => 5,
+            Self::Undefined => 6,
+        }
+    }
+
+    pub fn from_code(code: u8) -> Option<Self> {
+        Some(match code {
+            0 => Self::Empty,
+            1 => Self::Ord,
+            2 => Self::DRec,
+            3 => Self::Rec,
+            4 => Self::Neg,
+            5 => Self::Inf,
+            6 => Self::Undefined,
+            _ => return None,
+        })
+    }
+}
+
+impl Display for Terms {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for term in self.0.iter() {
+            write!(f, "{}", term.to_char())?;
+        }
+        Ok(())
+    }
+}
+
+// whitespace is skipped so that long expansions can be wrapped
+impl FromStr for Terms {
+    type Err = ParseTermsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.chars()
+            .filter(|c| !c.is_whitespace())
+            .map(|c| Term::from_char(c).ok_or(ParseTermsError::InvalidChar(c)))
+            .collect::<Result<_, _>>()
+            .map(Terms)
+    }
+}
+
+impl Terms {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = (self.0.len() as u64).to_le_bytes().to_vec();
+        out.resize(8 + (self.0.len() * 3).div_ceil(8), 0);
+        for (i, term) in self.0.iter().enumerate() {
+            // (a term can straddle two bytes)
+            let bit = i * 3;
+            let packed = (term.to_code() as u16) << (bit % 8);
+            out[8 + bit / 8] |= packed as u8;
+            if packed > 0xff {
+                out[8 + bit / 8 + 1] |= (packed >> 8) as u8;
+            }
+        }
+        out
+    }
+
+    // trailing bytes past the last term are ignored
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, DecodeTermsError> {
+        let header = bytes.get(..8).ok_or(DecodeTermsError::TooShort)?;
+        let len = u64::from_le_bytes(header.try_into().unwrap());
+        let len = usize::try_from(len).map_err(|_| DecodeTermsError::TooLong(len))?;
+        let body = &bytes[8..];
+        if len
+            .checked_mul(3)
+            .ok_or(DecodeTermsError::TooShort)?
+            .div_ceil(8)
+            > body.len()
+        {
+            return Err(DecodeTermsError::TooShort);
+        }
+        (0..len)
+            .map(|i| {
+                let bit = i * 3;
+                let lo = body[bit / 8] as u16;
+                let hi = body.get(bit / 8 + 1).copied().unwrap_or(0) as u16;
+                let code = (((hi << 8) | lo) >> (bit % 8)) as u8 & 0b111;
+                Term::from_code(code).ok_or(DecodeTermsError::InvalidCode(code))
+            })
+            .collect::<Result<_, _>>()
+            .map(Terms)
+    }
+}
+
+impl From<Vec<Term>> for Terms {
+    fn from(terms: Vec<Term>) -> Self {
+        Terms(terms)
+    }
+}
+
+impl FromIterator<Term> for Terms {
+    fn from_iter<I: IntoIterator<Item = Term>>(iter: I) -> Self {
+        Terms(iter.into_iter().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: [Term; 7] = [
+        Term::Empty,
+        Term::Ord,
+        Term::DRec,
+        Term::Rec,
+        Term::Neg,
+        Term::Inf,
+        Term::Undefined,
+    ];
+
+    // every term, cycled out to len (so each one lands on every bit offset eventually)
+    fn run(len: usize) -> Terms {
+        ALL.iter().copied().cycle().take(len).collect()
+    }
+
+    #[test]
+    fn text_round_trips() {
+        for len in 0..20 {
+            let terms = run(len);
+            assert_eq!(terms.to_string().parse::<Terms>(), Ok(terms));
+        }
+        assert_eq!(run(7).to_string(), "ø10/-∞!");
+        assert_eq!("1 1\n0 /".parse::<Terms>().unwrap().to_string(), "110/");
+        assert_eq!(
+            "11x".parse::<Terms>(),
+            Err(ParseTermsError::InvalidChar('x'))
+        );
+        assert_eq!(
+            ParseTermsError::InvalidChar('x').to_string(),
+            "'x' isn't a term"
+        );
+    }
+
+    #[test]
+    fn bytes_round_trip() {
+        // (3 bits a term, so most of these end partway through a byte)
+        for len in 0..=25 {
+            let terms = run(len);
+            let bytes = terms.to_bytes();
+            assert_eq!(bytes.len(), 8 + (len * 3).div_ceil(8));
+            assert_eq!(Terms::from_bytes(&bytes), Ok(terms));
+        }
+    }
+
+    #[test]
+    fn bytes_reject_bad_input() {
+        assert_eq!(Terms::from_bytes(&[]), Err(DecodeTermsError::TooShort));
+        assert_eq!(
+            Terms::from_bytes(&[3, 0, 0]),
+            Err(DecodeTermsError::TooShort)
+        );
+        // header says 3 terms (9 bits), but there's only one byte of them
+        let mut bytes = run(3).to_bytes();
+        bytes.pop();
+        assert_eq!(Terms::from_bytes(&bytes), Err(DecodeTermsError::TooShort));
+        // a header that would overflow counting its own bits
+        // (or that doesn't even fit in a usize)
+        let expected = match usize::try_from(u64::MAX) {
+            Ok(_) => DecodeTermsError::TooShort,
+            Err(_) => DecodeTermsError::TooLong(u64::MAX),
+        };
+        assert_eq!(Terms::from_bytes(&u64::MAX.to_le_bytes()), Err(expected));
+        let mut bytes = 1u64.to_le_bytes().to_vec();
+        bytes.push(0b111);
+        assert_eq!(
+            Terms::from_bytes(&bytes),
+            Err(DecodeTermsError::InvalidCode(7))
+        );
+        assert_eq!(
+            DecodeTermsError::InvalidCode(7).to_string(),
+            "7 isn't a term code"
+        );
+        // trailing bytes are fine
+        let mut bytes = run(5).to_bytes();
+        bytes.extend([0xff, 0xff]);
+        assert_eq!(Terms::from_bytes(&bytes), Ok(run(5)));
+    }
+}
@@ -1,6 +1,13 @@
-use num_bigint::{BigInt, Sign};
+use num_bigint::Sign;
+use num_rational::BigRational;
 
-use crate::{Term, unit::Unit};
+use crate::{
+    Term,
+    coeff::{self, Coeff},
+    interval::Interval,
+    reduce::{self, ReduceStats, Reducer},
+    unit::{UndefinedReason, Unit, lft::Lft},
+};
 
 // follows brabec's algorithm exactly if set to false
 // otherwise, allows egestion of 1s w/infinite endpoints
@@ -10,19 +17,125 @@ use crate::{Term, unit::Unit};
 // without tripping the flag
 pub const MORE_AGGRESSIVE: bool = true;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Arith {
-    pub mat: [BigInt; 8],
+    pub mat: [Coeff; 8],
     singularity: bool,
+    pub reducer: Reducer,
 }
 
 impl Arith {
-    pub fn create(mat: [BigInt; 8]) -> Self {
+    // mat is [a, b, c, d, e, f, g, h] for
+    // z = (axy + bx + cy + d) / (exy + fx + gy + h)
+    pub fn create(mat: [Coeff; 8]) -> Self {
         Self {
             mat,
             singularity: false,
+            reducer: Reducer::default(),
         }
     }
+
+    // z = (axy + bx + cy + d) / (exy + fx + gy + h)
+    // (same layout as create, just without having to spell out the array)
+    #[allow(clippy::too_many_arguments)]
+    pub fn bilinear(
+        a: impl Into<Coeff>,
+        b: impl Into<Coeff>,
+        c: impl Into<Coeff>,
+        d: impl Into<Coeff>,
+        e: impl Into<Coeff>,
+        f: impl Into<Coeff>,
+        g: impl Into<Coeff>,
+        h: impl Into<Coeff>,
+    ) -> Self {
+        Self::create([
+            a.into(),
+            b.into(),
+            c.into(),
+            d.into(),
+            e.into(),
+            f.into(),
+            g.into(),
+            h.into(),
+        ])
+    }
+
+    // z = x + y
+    pub fn add() -> Self {
+        Self::bilinear(0, 1, 1, 0, 0, 0, 0, 1)
+    }
+
+    // z = x - y
+    pub fn sub() -> Self {
+        Self::bilinear(0, 1, -1, 0, 0, 0, 0, 1)
+    }
+
+    // z = xy
+    pub fn mul() -> Self {
+        Self::bilinear(1, 0, 0, 0, 0, 0, 0, 1)
+    }
+
+    // z = x / y
+    pub fn div() -> Self {
+        Self::bilinear(0, 1, 0, 0, 0, 0, 1, 0)
+    }
+}
+
+// folding lfts into arith
+// each of these returns a new arith that computes the same thing
+// as if the lft were a separate unit wired up to that input (or output)
+// but without the extra unit (and extra cycle of delay)
+impl Arith {
+    // z(lft(x), y)
+    // substituting x = (px + q)/(rx + s) and multiplying through by (rx + s)
+    // maps each (xy, y) and (x, 1) coefficient pair through the lft's matrix
+    pub fn compose_x(&self, lft: &Lft) -> Self {
+        let [p, q, r, s] = &lft.mat;
+        let m = &self.mat;
+        Self::create([
+            &m[0] * p + &m[2] * r,
+            &m[1] * p + &m[3] * r,
+            &m[0] * q + &m[2] * s,
+            &m[1] * q + &m[3] * s,
+            &m[4] * p + &m[6] * r,
+            &m[5] * p + &m[7] * r,
+            &m[4] * q + &m[6] * s,
+            &m[5] * q + &m[7] * s,
+        ])
+    }
+
+    // z(x, lft(y))
+    // same deal, but with the (xy, x) and (y, 1) pairs
+    pub fn compose_y(&self, lft: &Lft) -> Self {
+        let [p, q, r, s] = &lft.mat;
+        let m = &self.mat;
+        Self::create([
+            &m[0] * p + &m[1] * r,
+            &m[0] * q + &m[1] * s,
+            &m[2] * p + &m[3] * r,
+            &m[2] * q + &m[3] * s,
+            &m[4] * p + &m[5] * r,
+            &m[4] * q + &m[5] * s,
+            &m[6] * p + &m[7] * r,
+            &m[6] * q + &m[7] * s,
+        ])
+    }
+
+    // lft(z(x, y))
+    // the new numerator is p * num + q * den, the new denominator r * num + s * den
+    pub fn compose_out(&self, lft: &Lft) -> Self {
+        let [p, q, r, s] = &lft.mat;
+        let m = &self.mat;
+        Self::create(std::array::from_fn(|i| {
+            let (num, den) = (&m[i % 4], &m[i % 4 + 4]);
+            if i < 4 {
+                p * num + q * den
+            } else {
+                r * num + s * den
+            }
+        }))
+    }
 }
 
 // manual egests; this way we can do feedbacks if we have to
@@ -48,10 +161,10 @@ impl Arith {
                 }
             }
             Term::DRec => {
-                self.mat[0] -= self.mat[4].clone();
-                self.mat[1] -= self.mat[5].clone();
-                self.mat[2] -= self.mat[6].clone();
-                self.mat[3] -= self.mat[7].clone();
+                coeff::sub_idx(&mut self.mat, 0, 4);
+                coeff::sub_idx(&mut self.mat, 1, 5);
+                coeff::sub_idx(&mut self.mat, 2, 6);
+                coeff::sub_idx(&mut self.mat, 3, 7);
                 self.mat.swap(0, 4);
                 self.mat.swap(1, 5);
                 self.mat.swap(2, 6);
@@ -64,10 +177,10 @@ impl Arith {
                 self.mat.swap(3, 7);
             }
             Term::Neg => {
-                self.mat[0] = -self.mat[0].clone();
-                self.mat[1] = -self.mat[1].clone();
-                self.mat[2] = -self.mat[2].clone();
-                self.mat[3] = -self.mat[3].clone();
+                self.mat[0] = -&self.mat[0];
+                self.mat[1] = -&self.mat[1];
+                self.mat[2] = -&self.mat[2];
+                self.mat[3] = -&self.mat[3];
             }
             Term::Undefined => {
                 self.mat = [
@@ -87,6 +200,7 @@ impl Arith {
 
 impl Unit for Arith {
     fn ingest_x(&mut self, x: Term) {
+        self.reducer.tick(&mut self.mat);
         match x {
             Term::Empty => {}
             Term::Ord => {
@@ -111,10 +225,10 @@ impl Unit for Arith {
                 self.mat.swap(1, 3);
                 self.mat.swap(4, 6);
                 self.mat.swap(5, 7);
-                self.mat[0] += self.mat[2].clone();
-                self.mat[1] += self.mat[3].clone();
-                self.mat[4] += self.mat[6].clone();
-                self.mat[5] += self.mat[7].clone();
+                coeff::add_idx(&mut self.mat, 0, 2);
+                coeff::add_idx(&mut self.mat, 1, 3);
+                coeff::add_idx(&mut self.mat, 4, 6);
+                coeff::add_idx(&mut self.mat, 5, 7);
             }
             Term::Rec => {
                 self.mat.swap(0, 2);
@@ -123,10 +237,10 @@ impl Unit for Arith {
                 self.mat.swap(5, 7);
             }
             Term::Neg => {
-                self.mat[0] = -self.mat[0].clone();
-                self.mat[1] = -self.mat[1].clone();
-                self.mat[4] = -self.mat[4].clone();
-                self.mat[5] = -self.mat[5].clone();
+                self.mat[0] = -&self.mat[0];
+                self.mat[1] = -&self.mat[1];
+                self.mat[4] = -&self.mat[4];
+                self.mat[5] = -&self.mat[5];
             }
             Term::Inf => {
                 self.mat[2] = self.mat[0].clone();
@@ -150,6 +264,7 @@ impl Unit for Arith {
     }
 
     fn ingest_y(&mut self, y: Term) {
+        self.reducer.tick(&mut self.mat);
         match y {
             Term::Empty => {}
             Term::Ord => {
@@ -174,10 +289,10 @@ impl Unit for Arith {
                 self.mat.swap(2, 3);
                 self.mat.swap(4, 5);
                 self.mat.swap(6, 7);
-                self.mat[0] += self.mat[1].clone();
-                self.mat[2] += self.mat[3].clone();
-                self.mat[4] += self.mat[5].clone();
-                self.mat[6] += self.mat[7].clone();
+                coeff::add_idx(&mut self.mat, 0, 1);
+                coeff::add_idx(&mut self.mat, 2, 3);
+                coeff::add_idx(&mut self.mat, 4, 5);
+                coeff::add_idx(&mut self.mat, 6, 7);
             }
             Term::Rec => {
                 self.mat.swap(0, 1);
@@ -186,10 +301,10 @@ impl Unit for Arith {
                 self.mat.swap(6, 7);
             }
             Term::Neg => {
-                self.mat[0] = -self.mat[0].clone();
-                self.mat[2] = -self.mat[2].clone();
-                self.mat[4] = -self.mat[4].clone();
-                self.mat[6] = -self.mat[6].clone();
+                self.mat[0] = -&self.mat[0];
+                self.mat[2] = -&self.mat[2];
+                self.mat[4] = -&self.mat[4];
+                self.mat[6] = -&self.mat[6];
             }
             Term::Inf => {
                 self.mat[1] = self.mat[0].clone();
@@ -213,17 +328,18 @@ impl Unit for Arith {
     }
 
     fn egest_z(&mut self) -> Term {
+        self.reducer.tick(&mut self.mat);
+
         // the 4 possibilities given x in [1, inf] and y in [1, inf]
-        let mut n0 = self.mat[0].clone();
-        let mut n1 = self.mat[0].clone() + self.mat[1].clone();
-        let mut n2 = self.mat[0].clone() + self.mat[2].clone();
-        let mut n3 =
-            self.mat[0].clone() + self.mat[1].clone() + self.mat[2].clone() + self.mat[3].clone();
-        let mut d0 = self.mat[4].clone();
-        let mut d1 = self.mat[4].clone() + self.mat[5].clone();
-        let mut d2 = self.mat[4].clone() + self.mat[6].clone();
-        let mut d3 =
-            self.mat[4].clone() + self.mat[5].clone() + self.mat[6].clone() + self.mat[7].clone();
+        let m = &self.mat;
+        let mut n0 = m[0].clone();
+        let mut n1 = &m[0] + &m[1];
+        let mut n2 = &m[0] + &m[2];
+        let mut n3 = &n1 + &m[2] + &m[3];
+        let mut d0 = m[4].clone();
+        let mut d1 = &m[4] + &m[5];
+        let mut d2 = &m[4] + &m[6];
+        let mut d3 = &d1 + &m[6] + &m[7];
         let signs = [
             crate::rationalize(&mut n0, &mut d0),
             crate::rationalize(&mut n1, &mut d1),
@@ -247,6 +363,7 @@ impl Unit for Arith {
         // if the expression is undefined
         // then what else can we say
         if self.mat.iter().all(|i| *i == 0.into()) {
+            trace!("arith: matrix is all zeros, undefined");
             return Term::Undefined;
         }
 
@@ -263,10 +380,10 @@ impl Unit for Arith {
         {
             // handling negative infinity :P
             if signs.iter().all(|s| *s == Sign::Minus) {
-                self.mat[0] = -self.mat[0].clone();
-                self.mat[1] = -self.mat[1].clone();
-                self.mat[2] = -self.mat[2].clone();
-                self.mat[3] = -self.mat[3].clone();
+                self.mat[0] = -&self.mat[0];
+                self.mat[1] = -&self.mat[1];
+                self.mat[2] = -&self.mat[2];
+                self.mat[3] = -&self.mat[3];
                 return Term::Neg;
             }
             return Term::Inf;
@@ -290,21 +407,17 @@ impl Unit for Arith {
             if signs.iter().all(|s| *s == Sign::Minus) {
                 self.egest(Term::Neg);
                 return Term::Neg;
-            } else if (n0.clone() >> 1) >= d0
-                && (n1.clone() >> 1) >= d1
-                && (n2.clone() >> 1) >= d2
-                && (n3.clone() >> 1) >= d3
-            {
+            } else if (&n0 >> 1) >= d0 && (&n1 >> 1) >= d1 && (&n2 >> 1) >= d2 && (&n3 >> 1) >= d3 {
                 self.egest(Term::Ord);
                 return Term::Ord;
             } else if n0 >= d0
                 && n1 >= d1
                 && n2 >= d2
                 && n3 >= d3
-                && (n0.clone() >> 1) < d0
-                && (n1.clone() >> 1) < d1
-                && (n2.clone() >> 1) < d2
-                && (n3.clone() >> 1) < d3
+                && (&n0 >> 1) < d0
+                && (&n1 >> 1) < d1
+                && (&n2 >> 1) < d2
+                && (&n3 >> 1) < d3
             {
                 self.egest(Term::DRec);
                 return Term::DRec;
@@ -314,25 +427,27 @@ impl Unit for Arith {
             }
             // the following are SPECULATIVE
             else if d0 < n0
-                && n0 < (d0.clone() << 2)
+                && n0 < (&d0 << 2)
                 && d1 < n1
-                && n1 < (d1.clone() << 2)
+                && n1 < (&d1 << 2)
                 && d2 < n2
-                && n2 < (d2.clone() << 2)
+                && n2 < (&d2 << 2)
                 && d3 < n3
-                && n3 < (d3.clone() << 2)
+                && n3 < (&d3 << 2)
             {
+                trace!("arith: speculating 1 (z in (1, 4))");
                 self.egest(Term::Ord);
                 return Term::Ord;
-            } else if d0 < (n0.clone() << 1)
-                && n0 < d0.clone() << 1
-                && d1 < (n1.clone() << 1)
-                && n1 < d1.clone() << 1
-                && d2 < (n2.clone() << 1)
-                && n2 < d2.clone() << 1
-                && d3 < (n3.clone() << 1)
-                && n3 < d3.clone() << 1
+            } else if d0 < (&n0 << 1)
+                && n0 < &d0 << 1
+                && d1 < (&n1 << 1)
+                && n1 < &d1 << 1
+                && d2 < (&n2 << 1)
+                && n2 < &d2 << 1
+                && d3 < (&n3 << 1)
+                && n3 < &d3 << 1
             {
+                trace!("arith: speculating 0 (z in (1/2, 2)), entering the singularity");
                 self.egest(Term::DRec);
                 // we're launching ourselves into the singularity
                 self.singularity = true;
@@ -342,28 +457,30 @@ impl Unit for Arith {
             // not well defined :D
             if (!self.singularity || MORE_AGGRESSIVE)
                     // 0s ARE allowed in the denominator; we send to [-inf, -1) U (1, inf]
-                    && n0.clone().into_parts().1 < d0.clone().into_parts().1
-                    && n1.clone().into_parts().1 < d1.clone().into_parts().1
-                    && n2.clone().into_parts().1 < d2.clone().into_parts().1
-                    && n3.clone().into_parts().1 < d3.clone().into_parts().1
+                    && n0.abs() < d0.abs()
+                    && n1.abs() < d1.abs()
+                    && n2.abs() < d2.abs()
+                    && n3.abs() < d3.abs()
             {
+                trace!("arith: ill-defined, speculating / (|z| < 1), entering the singularity");
                 self.egest(Term::Rec);
                 // we're launching ourselves into the singularity
                 self.singularity = true;
                 return Term::Rec;
             }
             if self.singularity || MORE_AGGRESSIVE {
-                let a1: BigInt = d0.clone() << 1;
-                let a2: BigInt = d1.clone() << 1;
-                let a3: BigInt = d2.clone() << 1;
-                let a4: BigInt = d3.clone() << 1;
+                let a1 = &d0 << 1;
+                let a2 = &d1 << 1;
+                let a3 = &d2 << 1;
+                let a4 = &d3 << 1;
                 // brabec says to use [-infinity, -2) U (2, infinity]
                 // i don't see any reason to not make that fully closed
-                if a1.into_parts().1 <= n0.clone().into_parts().1
-                    && a2.into_parts().1 <= n1.clone().into_parts().1
-                    && a3.into_parts().1 <= n2.clone().into_parts().1
-                    && a4.into_parts().1 <= n3.clone().into_parts().1
+                if a1.abs() <= n0.abs()
+                    && a2.abs() <= n1.abs()
+                    && a3.abs() <= n2.abs()
+                    && a4.abs() <= n3.abs()
                 {
+                    trace!("arith: ill-defined, speculating 1 (|z| >= 2)");
                     self.egest(Term::Ord);
                     return Term::Ord;
                 }
@@ -372,4 +489,124 @@ impl Unit for Arith {
 
         Term::Empty
     }
+
+    fn max_bits(&self) -> u64 {
+        reduce::max_bits(&self.mat)
+    }
+
+    fn matrix_bits(&self) -> Vec<(&'static str, u64)> {
+        vec![("mat", self.max_bits())]
+    }
+
+    fn reduce_stats(&self) -> ReduceStats {
+        self.reducer.stats
+    }
+
+    // (the only way we get there is an all-zero matrix)
+    fn undefined_reason(&self) -> UndefinedReason {
+        UndefinedReason::Indeterminate
+    }
+
+    // a bilinear fraction is monotone in x and in y separately
+    // so as long as the denominator keeps its sign, the extremes are at the corners
+    fn pending(&self) -> Option<Interval> {
+        let m = &self.mat;
+        let corners = [
+            (m[0].clone(), m[4].clone()),
+            (&m[0] + &m[1], &m[4] + &m[5]),
+            (&m[0] + &m[2], &m[4] + &m[6]),
+            (&m[0] + &m[1] + &m[2] + &m[3], &m[4] + &m[5] + &m[6] + &m[7]),
+        ];
+        let sign = corners[0].1.sign();
+        if sign == Sign::NoSign || corners.iter().any(|(_, d)| d.sign() != sign) {
+            return None;
+        }
+        let values = corners
+            .into_iter()
+            .map(|(n, d)| BigRational::new(n.into(), d.into()));
+        let lo = values.clone().min()?;
+        let hi = values.max()?;
+        Some(Interval::closed(lo, hi))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use num_bigint::BigInt;
+
+    use super::*;
+
+    // points on the projective line, oo and all
+    fn points() -> Vec<(BigInt, BigInt)> {
+        [(0, 1), (1, 1), (-3, 1), (1, 2), (-5, 7), (1, 0)]
+            .into_iter()
+            .map(|(n, d)| (n.into(), d.into()))
+            .collect()
+    }
+
+    // n1/d1 = n2/d2, without caring about common factors
+    // (0/0 only equals itself)
+    fn same((n1, d1): &(BigInt, BigInt), (n2, d2): &(BigInt, BigInt)) -> bool {
+        let undefined =
+            |n: &BigInt, d: &BigInt| n.sign() == Sign::NoSign && d.sign() == Sign::NoSign;
+        undefined(n1, d1) == undefined(n2, d2) && n1 * d2 == n2 * d1
+    }
+
+    // z at x = xn/xd, y = yn/yd, by plugging in
+    fn eval(
+        arith: &Arith,
+        (xn, xd): &(BigInt, BigInt),
+        (yn, yd): &(BigInt, BigInt),
+    ) -> (BigInt, BigInt) {
+        let m = arith.mat.clone().map(|c| c.to_bigint());
+        let half =
+            |m: &[BigInt]| &m[0] * xn * yn + &m[1] * xn * yd + &m[2] * xd * yn + &m[3] * xd * yd;
+        (half(&m[..4]), half(&m[4..]))
+    }
+
+    fn lfts() -> Vec<Lft> {
+        vec![
+            Lft::create([2.into(), 1.into(), 1.into(), 3.into()]),
+            Lft::reciprocal(),
+            Lft::affine(-3, 5),
+            // (degenerate, it's just 2)
+            Lft::create([2.into(), 4.into(), 1.into(), 2.into()]),
+        ]
+    }
+
+    #[test]
+    fn composing_substitutes() {
+        let z = Arith::bilinear(1, 2, -1, 3, 2, 0, 1, 1);
+        for lft in lfts() {
+            let (on_x, on_y, on_out) = (z.compose_x(&lft), z.compose_y(&lft), z.compose_out(&lft));
+            for x in points() {
+                let lft_x = lft.eval(&x.0, &x.1);
+                for y in points() {
+                    let lft_y = lft.eval(&y.0, &y.1);
+                    assert!(same(&eval(&on_x, &x, &y), &eval(&z, &lft_x, &y)));
+                    assert!(same(&eval(&on_y, &x, &y), &eval(&z, &x, &lft_y)));
+                    let (n, d) = eval(&z, &x, &y);
+                    assert!(same(&eval(&on_out, &x, &y), &lft.eval(&n, &d)));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn composing_by_hand() {
+        // (2x + 1) + y
+        let composed = Arith::add().compose_x(&Lft::affine(2, 1));
+        assert_eq!(composed.mat, Arith::bilinear(0, 2, 1, 1, 0, 0, 0, 1).mat);
+        // x * (1/y) = x / y
+        let composed = Arith::mul().compose_y(&Lft::reciprocal());
+        assert_eq!(composed.mat, Arith::bilinear(0, 1, 0, 0, 0, 0, 1, 0).mat);
+        // 1/(x - y)
+        let composed = Arith::sub().compose_out(&Lft::reciprocal());
+        assert_eq!(composed.mat, Arith::bilinear(0, 0, 0, 1, 0, 1, -1, 0).mat);
+        // (2x + 4)/(x + 2) + y, with the degenerate lft
+        // (i.e. (x + 2)(y + 2) / (x + 2), so 2 + y except at x = -2)
+        let two = Lft::create([2.into(), 4.into(), 1.into(), 2.into()]);
+        let composed = Arith::add().compose_x(&two);
+        assert_eq!(composed.mat, Arith::bilinear(1, 2, 2, 4, 0, 1, 0, 2).mat);
+    }
 }
@@ -1,41 +1,123 @@
 use std::fmt::Debug;
 
-use num_bigint::BigInt;
+use num_bigint::Sign;
 
-use crate::{Term, unit::Unit};
+use crate::{
+    Term,
+    coeff::{self, Coeff},
+    reduce::{self, ReduceStats, Reducer},
+    unit::{UndefinedReason, Unit},
+};
 
 pub mod consts {
-    use crate::unit::cfrac::FromCFrac;
+    use crate::unit::cfrac::{CFracSource, FromCFrac};
 
     pub fn e() -> FromCFrac {
-        FromCFrac {
-            iter: {
-                let mut i = -1;
-                Box::new(std::iter::from_fn(move || {
-                    i += 1;
-                    if i == 0 {
-                        Some((2, 1, 1, 1))
-                    } else if i % 3 == 2 {
-                        Some((2 + 2 * (i / 3), 1, 1, 1))
-                    } else {
-                        Some((1, 1, 1, 1))
-                    }
-                }))
-            },
-            mat: [1.into(), 0.into(), 0.into(), 1.into()],
-        }
+        FromCFrac::create(
+            [1.into(), 0.into(), 0.into(), 1.into()],
+            CFracSource::E { index: 0 },
+        )
     }
 
     pub fn pi() -> FromCFrac {
-        FromCFrac {
-            iter: {
-                let mut i = 0;
-                Box::new(std::iter::from_fn(move || {
-                    i += 1;
-                    Some((i * 2 - 1, 1, i * i, 1))
-                }))
-            },
-            mat: [0.into(), 4.into(), 1.into(), 0.into()],
+        FromCFrac::create(
+            [0.into(), 4.into(), 1.into(), 0.into()],
+            CFracSource::Pi { index: 0 },
+        )
+    }
+}
+
+// where the (p, q, r, s) terms come from
+// the named ones carry their position explicitly (rather than hiding it in a closure)
+// so that they can be inspected, serialized, and so on
+// an Iter can be anything, but it's opaque
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum CFracSource {
+    // e = [2; 1, 2, 1, 1, 4, 1, 1, 6, ...]
+    E {
+        index: i64,
+    },
+    // pi = 4/(1 + 1^2/(3 + 2^2/(5 + ...))) (the 4/x is in the starting matrix)
+    Pi {
+        index: i64,
+    },
+    // a plain old simple cfrac [a0; a1, a2, ...]
+    // ingests oo once it runs out
+    Simple {
+        terms: Vec<i64>,
+        index: usize,
+    },
+    // (closures can't be serialized; trying to is an error)
+    #[cfg_attr(feature = "serde", serde(skip))]
+    Iter(Box<dyn CFracIter>),
+}
+
+// same deal as CLogFn: a term iterator that can be cloned and sent between threads
+pub trait CFracIter: Iterator<Item = (i64, i64, i64, i64)> + Send {
+    fn clone_box(&self) -> Box<dyn CFracIter>;
+}
+
+impl<I: Iterator<Item = (i64, i64, i64, i64)> + Clone + Send + 'static> CFracIter for I {
+    fn clone_box(&self) -> Box<dyn CFracIter> {
+        Box::new(self.clone())
+    }
+}
+
+impl Clone for Box<dyn CFracIter> {
+    fn clone(&self) -> Self {
+        (**self).clone_box()
+    }
+}
+
+impl CFracSource {
+    pub fn boxed(
+        iter: impl Iterator<Item = (i64, i64, i64, i64)> + Clone + Send + 'static,
+    ) -> Self {
+        Self::Iter(Box::new(iter))
+    }
+}
+
+impl Iterator for CFracSource {
+    type Item = (i64, i64, i64, i64);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            Self::E { index } => {
+                let i = *index;
+                *index += 1;
+                if i == 0 {
+                    Some((2, 1, 1, 1))
+                } else if i % 3 == 2 {
+                    Some((2 + 2 * (i / 3), 1, 1, 1))
+                } else {
+                    Some((1, 1, 1, 1))
+                }
+            }
+            Self::Pi { index } => {
+                *index += 1;
+                let i = *index;
+                Some((i * 2 - 1, 1, i * i, 1))
+            }
+            Self::Simple { terms, index } => {
+                let t = terms.get(*index)?;
+                *index += 1;
+                Some((*t, 1, 1, 1))
+            }
+            Self::Iter(iter) => iter.next(),
+        }
+    }
+}
+
+impl Debug for CFracSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::E { index } => write!(f, "E {{ index: {} }}", index),
+            Self::Pi { index } => write!(f, "Pi {{ index: {} }}", index),
+            Self::Simple { terms, index } => {
+                write!(f, "Simple {{ terms: {:?}, index: {} }}", terms, index)
+            }
+            Self::Iter(_) => write!(f, "Iter (internal iter cannot be inspected)"),
         }
     }
 }
@@ -43,42 +125,47 @@ pub mod consts {
 // generalized cfrac input gives us a lot of nice things
 // in particular, we can use this to easily get nice things like e^q for q in Q
 // unfortunately, if we want to get something like e^x for continued logarithm x, we need a different method
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct FromCFrac {
     // each (p/q, r/s) replaces x with p/q + (r/s)/x
     // we love us some generalized continued fractions
-    pub iter: Box<dyn Iterator<Item = (i64, i64, i64, i64)>>,
-    pub mat: [BigInt; 4],
+    pub source: CFracSource,
+    pub mat: [Coeff; 4],
+    pub reducer: Reducer,
 }
 
-impl Debug for FromCFrac {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "FromCFrac: (internal iter cannot be inspected); mat: {:?}",
-            self.mat
+impl FromCFrac {
+    pub fn create(mat: [Coeff; 4], source: CFracSource) -> Self {
+        Self {
+            source,
+            mat,
+            reducer: Reducer::default(),
+        }
+    }
+
+    // [a0; a1, a2, ...]
+    pub fn simple(terms: Vec<i64>) -> Self {
+        Self::create(
+            [1.into(), 0.into(), 0.into(), 1.into()],
+            CFracSource::Simple { terms, index: 0 },
         )
     }
 }
 
-impl Unit for FromCFrac {
-    // we don't ingest clog terms
-    // we do ingest from iter
-    // but that's once per egest
-    fn ingest_x(&mut self, _x: Term) {}
-    fn ingest_y(&mut self, _y: Term) {}
-
-    fn egest_z(&mut self) -> Term {
-        let terms = self.iter.next();
+impl FromCFrac {
+    fn ingest_next(&mut self) {
+        let terms = self.source.next();
         // ingest from iter
         match terms {
             Some((p, q, r, s)) => {
                 let i = self.mat[0].clone();
                 self.mat[0] *= p * s;
-                self.mat[0] += self.mat[1].clone() * q * s;
+                self.mat[0] += &self.mat[1] * q * s;
                 self.mat[1] = i * r * q;
                 let i = self.mat[2].clone();
                 self.mat[2] *= p * s;
-                self.mat[2] += self.mat[3].clone() * q * s;
+                self.mat[2] += &self.mat[3] * q * s;
                 self.mat[3] = i * r * q;
             }
             None => {
@@ -87,53 +174,31 @@ impl Unit for FromCFrac {
                 self.mat[3] = self.mat[2].clone();
             }
         }
-        // // ugh if the cfrac is generalized rather than simple
-        // // the gcd grows, and can get annoyingly big
-        // // like up to half the bits might be redundant
-        // // but i can't do anything about it because calculating the gcd scales linearly with the number of bits
-        // // and it's not worth it to do like 100 divisions per cycle
-        // {
-        //     fn gcd(mut a: BigInt, mut b: BigInt) -> BigInt {
-        //         let mut i = 0;
-        //         while b != 0.into() {
-        //             i += 1;
-        //             let t = b.clone();
-        //             b = a % b;
-        //             a = t;
-        //         }
-        //         println!("{} iters to find the gcd", i);
-        //         a
-        //     }
-        //     let g = gcd(
-        //         self.mat[0].clone(),
-        //         gcd(
-        //             self.mat[1].clone(),
-        //             gcd(self.mat[2].clone(), self.mat[3].clone()),
-        //         ),
-        //     );
-        //     self.mat[0] /= g.clone();
-        //     self.mat[1] /= g.clone();
-        //     self.mat[2] /= g.clone();
-        //     self.mat[3] /= g.clone();
-        //     println!("{:?} | {:?}", self.mat, g);
-        // }
-
-        // nowww we egest a term
+        // if the cfrac is generalized rather than simple
+        // the gcd grows, and can get annoyingly big
+        // like up to half the bits might be redundant
+        // calculating the gcd scales linearly with the number of bits
+        // so we only do it every so often (see reduce.rs)
+        self.reducer.tick(&mut self.mat);
+    }
+
+    fn egest_from_mat(&mut self) -> Term {
         let n0 = self.mat[0].clone();
-        let n1 = self.mat[0].clone() + self.mat[1].clone();
+        let n1 = &self.mat[0] + &self.mat[1];
         let d0 = self.mat[2].clone();
-        let d1 = self.mat[2].clone() + self.mat[3].clone();
+        let d1 = &self.mat[2] + &self.mat[3];
         if n0 == 0.into() && n1 == 0.into() && d0 == 0.into() && d1 == 0.into() {
+            trace!("from_cfrac: undefined");
             return Term::Undefined;
         }
         if d0 == 0.into() && d1 == 0.into() {
             return Term::Inf;
         }
         if (self.mat[0] < 0.into()) != (self.mat[2] < 0.into()) {
-            self.mat[0] = -self.mat[0].clone();
-            self.mat[1] = -self.mat[1].clone();
+            self.mat[0] = -&self.mat[0];
+            self.mat[1] = -&self.mat[1];
             return Term::Neg;
-        } else if (n0.clone() >> 1) >= d0 && (n1.clone() >> 1) >= d1 {
+        } else if (&n0 >> 1) >= d0 && (&n1 >> 1) >= d1 {
             if !self.mat[0].bit(0) && !self.mat[1].bit(0) {
                 self.mat[0] >>= 1;
                 self.mat[1] >>= 1;
@@ -142,9 +207,9 @@ impl Unit for FromCFrac {
                 self.mat[3] <<= 1;
             }
             return Term::Ord;
-        } else if n0 >= d0 && n1 >= d1 && (n0.clone() >> 1) < d0 && (n1.clone() >> 1) < d1 {
-            self.mat[0] -= self.mat[2].clone();
-            self.mat[1] -= self.mat[3].clone();
+        } else if n0 >= d0 && n1 >= d1 && (&n0 >> 1) < d0 && (&n1 >> 1) < d1 {
+            coeff::sub_idx(&mut self.mat, 0, 2);
+            coeff::sub_idx(&mut self.mat, 1, 3);
             self.mat.swap(0, 2);
             self.mat.swap(1, 3);
             return Term::DRec;
@@ -156,3 +221,102 @@ impl Unit for FromCFrac {
         Term::Empty
     }
 }
+
+impl Unit for FromCFrac {
+    // we don't ingest clog terms
+    // we do ingest from iter
+    // but that's once per egest
+    fn ingest_x(&mut self, _x: Term) {}
+    fn ingest_y(&mut self, _y: Term) {}
+
+    fn egest_z(&mut self) -> Term {
+        // (pulling a term from the source counts as an ingest, see ingest_next)
+        self.reducer.tick(&mut self.mat);
+        // we only ingest from iter when there's nothing to egest
+        // each generalized term is worth a couple of bits, and an egest only takes about one off
+        // so ingesting every time would leave the matrix growing forever with bits we never egest
+        // (and no amount of gcd-ing gets rid of those)
+        // but while x = oo takes us to oo we ingest first anyway
+        // since that's where a finite cfrac ends, and egesting first would just be guessing how big we are
+        let bounded = self.mat[2].sign() != Sign::NoSign
+            && self.mat[2].sign() == (&self.mat[2] + &self.mat[3]).sign();
+        if !bounded {
+            self.ingest_next();
+            return self.egest_from_mat();
+        }
+        match self.egest_from_mat() {
+            Term::Empty => {
+                self.ingest_next();
+                self.egest_from_mat()
+            }
+            term => term,
+        }
+    }
+
+    fn max_bits(&self) -> u64 {
+        reduce::max_bits(&self.mat)
+    }
+
+    fn matrix_bits(&self) -> Vec<(&'static str, u64)> {
+        vec![("mat", self.max_bits())]
+    }
+
+    fn reduce_stats(&self) -> ReduceStats {
+        self.reducer.stats
+    }
+
+    fn undefined_reason(&self) -> UndefinedReason {
+        UndefinedReason::Indeterminate
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the real terms, ingesting a source term before every egest like we used to
+    fn eagerly(mut cfrac: FromCFrac, n: usize) -> Vec<Term> {
+        std::iter::from_fn(|| {
+            cfrac.ingest_next();
+            Some(cfrac.egest_from_mat())
+        })
+        .filter(|term| *term != Term::Empty)
+        .take(n)
+        .collect()
+    }
+
+    fn lazily(mut cfrac: FromCFrac, n: usize) -> Vec<Term> {
+        std::iter::from_fn(|| Some(cfrac.egest_z()))
+            .filter(|term| *term != Term::Empty)
+            .take(n)
+            .collect()
+    }
+
+    #[test]
+    fn lazy_ingesting_egests_the_same_terms() {
+        assert_eq!(lazily(consts::pi(), 500), eagerly(consts::pi(), 500));
+        assert_eq!(lazily(consts::e(), 500), eagerly(consts::e(), 500));
+        for terms in [
+            vec![2],
+            vec![0, 2],
+            vec![3],
+            vec![1, 2, 3],
+            vec![-2],
+            vec![0],
+        ] {
+            let simple = FromCFrac::simple(terms);
+            assert_eq!(lazily(simple.clone(), 12), eagerly(simple, 12));
+        }
+    }
+
+    #[test]
+    fn finite_cfracs_end_without_extra_terms() {
+        use Term::*;
+        // (2 = 2 * (1 + 1/oo), nothing else before the oo)
+        assert_eq!(lazily(FromCFrac::simple(vec![2]), 4), [Ord, DRec, Inf, Inf]);
+        assert_eq!(
+            lazily(FromCFrac::simple(vec![0, 2]), 5),
+            [Rec, Ord, DRec, Inf, Inf]
+        );
+    }
+}
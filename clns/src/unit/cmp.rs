@@ -1,11 +1,14 @@
-use num_bigint::BigInt;
+use std::cmp::Ordering;
 
 use crate::{
-    Term,
+    Error, Term,
+    interval::Interval,
+    reduce::ReduceStats,
     unit::{Unit, arith::Arith, lft::Lft},
 };
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Compare {
     arith: Arith,
     lft: Lft,
@@ -14,26 +17,27 @@ pub struct Compare {
 impl Compare {
     pub fn create() -> Self {
         Compare {
-            arith: Arith::create([
-                0.into(),
-                1.into(),
-                (-1).into(),
-                0.into(),
-                0.into(),
-                0.into(),
-                0.into(),
-                1.into(),
-            ]),
+            arith: Arith::sub(),
             lft: Lft {
-                mat: [1.into(), 0.into(), 0.into(), 1.into()],
                 egest_enabled: false,
+                ..Lft::identity()
             },
         }
     }
 
-    pub fn cmp(&self) -> (BigInt, BigInt) {
-        // let (a, b) = self.lft.intervals();
-        todo!()
+    // how x compares to y, going by the sign of x - y
+    // Undecided until the difference has been narrowed down to one side of 0
+    // (which never happens if x = y and neither is exact, or if the difference is undefined)
+    pub fn cmp(&self) -> Result<Ordering, Error> {
+        if self.lft.is_zero() {
+            Ok(Ordering::Equal)
+        } else if self.lft.is_positive() {
+            Ok(Ordering::Greater)
+        } else if self.lft.is_negative() {
+            Ok(Ordering::Less)
+        } else {
+            Err(Error::Undecided)
+        }
     }
 }
 
@@ -51,4 +55,26 @@ impl Unit for Compare {
     fn egest_z(&mut self) -> Term {
         Term::Empty
     }
+
+    fn max_bits(&self) -> u64 {
+        self.arith.max_bits().max(self.lft.max_bits())
+    }
+
+    fn matrix_bits(&self) -> Vec<(&'static str, u64)> {
+        vec![
+            ("arith", self.arith.max_bits()),
+            ("lft", self.lft.max_bits()),
+        ]
+    }
+
+    fn reduce_stats(&self) -> ReduceStats {
+        let mut stats = self.arith.reduce_stats();
+        stats.merge(&self.lft.reduce_stats());
+        stats
+    }
+
+    // (we never egest, so this is where x - y is)
+    fn pending(&self) -> Option<Interval> {
+        self.lft.interval()
+    }
 }
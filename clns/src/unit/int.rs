@@ -1,6 +1,7 @@
 use crate::{
     Term,
-    unit::{Unit, arith::Arith, lft::Lft},
+    reduce::ReduceStats,
+    unit::{UndefinedReason, Unit, arith::Arith, lft::Lft},
 };
 
 // as defined here, modulus computes
@@ -14,7 +15,8 @@ use crate::{
 // for continuous things like arithmetic, we can simply retract and get arbitrarily close
 // for discontinuous things like this, we can simply be wrong, and there's no way around it
 // without demanding that we wait until inputs are fully ingested and settled
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Modulo {
     div: Arith,
     quotient: Lft,
@@ -26,16 +28,7 @@ pub struct Modulo {
 impl Modulo {
     pub fn create() -> Self {
         Self {
-            div: Arith::create([
-                0.into(),
-                1.into(),
-                0.into(),
-                0.into(),
-                0.into(),
-                0.into(),
-                1.into(),
-                0.into(),
-            ]),
+            div: Arith::div(),
             quotient: Lft {
                 egest_enabled: false,
                 ..Lft::identity()
@@ -71,20 +64,12 @@ impl Unit for Modulo {
         // although we shouldn't ever be off by more than 1 i think
         // cause that only happens if the input is VERY malformed
         // println!("{:?}", self.quotient.trunc());
-        if let None = self.out {
+        if self.out.is_none() {
             // if x or y is undefined, then so are we
             // also, oo % x = !!!!
             if self.quotient.is_inf() || self.quotient.is_undefined() {
-                self.out = Some(Arith::create([
-                    0.into(),
-                    0.into(),
-                    0.into(),
-                    0.into(),
-                    0.into(),
-                    0.into(),
-                    0.into(),
-                    0.into(),
-                ]))
+                debug!("modulo: quotient is oo or undefined, so we're undefined");
+                self.out = Some(Arith::bilinear(0, 0, 0, 0, 0, 0, 0, 0))
             }
             // i'm special casing it so that x % +-oo = x
             // unless x = +-oo, since +-oo % +-oo = !
@@ -92,29 +77,11 @@ impl Unit for Modulo {
                 // xy/y; y=oo so it just becomes x
                 // the other expr gives (x - 0y)/1
                 // which evals to !!! at y=oo because of the implied leading xy term
-                self.out = Some(Arith::create([
-                    1.into(),
-                    0.into(),
-                    0.into(),
-                    0.into(),
-                    0.into(),
-                    0.into(),
-                    1.into(),
-                    0.into(),
-                ]));
+                self.out = Some(Arith::bilinear(1, 0, 0, 0, 0, 0, 1, 0));
             }
             if let Some(val) = self.quotient.trunc() {
-                println!("we're settling on trunc val {}", val);
-                self.out = Some(Arith::create([
-                    0.into(),
-                    1.into(),
-                    -val,
-                    0.into(),
-                    0.into(),
-                    0.into(),
-                    0.into(),
-                    1.into(),
-                ]));
+                debug!("modulo: settling on trunc val {}", val);
+                self.out = Some(Arith::bilinear(0, 1, -val, 0, 0, 0, 0, 1));
             }
         }
     }
@@ -125,4 +92,38 @@ impl Unit for Modulo {
             .map(|arith| arith.egest_z())
             .unwrap_or(Term::Empty)
     }
+
+    fn max_bits(&self) -> u64 {
+        [&self.quotient, &self.x, &self.y]
+            .iter()
+            .map(|lft| lft.max_bits())
+            .chain(self.out.iter().map(|out| out.max_bits()))
+            .fold(self.div.max_bits(), u64::max)
+    }
+
+    fn matrix_bits(&self) -> Vec<(&'static str, u64)> {
+        let mut bits = vec![
+            ("div", self.div.max_bits()),
+            ("quotient", self.quotient.max_bits()),
+            ("x", self.x.max_bits()),
+            ("y", self.y.max_bits()),
+        ];
+        bits.extend(self.out.iter().map(|out| ("out", out.max_bits())));
+        bits
+    }
+
+    fn reduce_stats(&self) -> ReduceStats {
+        let mut stats = self.div.reduce_stats();
+        for lft in [&self.quotient, &self.x, &self.y] {
+            stats.merge(&lft.reduce_stats());
+        }
+        if let Some(out) = &self.out {
+            stats.merge(&out.reduce_stats());
+        }
+        stats
+    }
+
+    fn undefined_reason(&self) -> UndefinedReason {
+        UndefinedReason::BadModulo
+    }
 }
@@ -1,21 +1,58 @@
+use std::cmp::Ordering;
+
 use num_bigint::{BigInt, Sign};
+use num_rational::BigRational;
 
-use crate::{Term, unit::Unit};
+use crate::{
+    Term,
+    coeff::{self, Coeff},
+    interval::{Bound, Interval},
+    reduce::{self, ReduceStats, Reducer},
+    unit::{UndefinedReason, Unit},
+};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Lft {
-    pub mat: [BigInt; 4],
+    pub mat: [Coeff; 4],
     pub egest_enabled: bool,
+    pub reducer: Reducer,
 }
 
 impl Lft {
-    pub fn identity() -> Self {
+    // mat is [a, b, c, d] for
+    // z = (ax + b) / (cx + d)
+    pub fn create(mat: [Coeff; 4]) -> Self {
         Self {
-            mat: [1.into(), 0.into(), 0.into(), 1.into()],
+            mat,
             egest_enabled: true,
+            reducer: Reducer::default(),
         }
     }
 
+    // z = x
+    pub fn identity() -> Self {
+        Self::create([1.into(), 0.into(), 0.into(), 1.into()])
+    }
+
+    // z = px + q
+    pub fn affine(p: impl Into<Coeff>, q: impl Into<Coeff>) -> Self {
+        Self::create([p.into(), q.into(), 0.into(), 1.into()])
+    }
+
+    // z = 1 / x
+    pub fn reciprocal() -> Self {
+        Self::create([0.into(), 1.into(), 1.into(), 0.into()])
+    }
+
+    // z = num / den, regardless of x
+    // (both columns are the same, so x=1 and x=oo agree from the start)
+    // meant to be used as a source, i.e. without an x input
+    pub fn from_rational(num: impl Into<Coeff>, den: impl Into<Coeff>) -> Self {
+        let (num, den) = (num.into(), den.into());
+        Self::create([num.clone(), num, den.clone(), den])
+    }
+
     // all the methods of the form is_*
     // require BOTH endpoints to be *
     // an LFT can be neither zero, nor positive, nor negative
@@ -38,97 +75,138 @@ impl Lft {
 
     pub fn is_nonnegative(&self) -> bool {
         !((self.mat[0].sign() == Sign::Minus) ^ (self.mat[2].sign() == Sign::Minus))
-            && !(((self.mat[0].clone() + self.mat[1].clone()).sign() == Sign::Minus)
-                ^ ((self.mat[2].clone() + self.mat[3].clone()).sign() == Sign::Minus))
+            && !(((&self.mat[0] + &self.mat[1]).sign() == Sign::Minus)
+                ^ ((&self.mat[2] + &self.mat[3]).sign() == Sign::Minus))
     }
 
     pub fn is_nonpositive(&self) -> bool {
         ((self.mat[0].sign() == Sign::Minus) ^ (self.mat[2].sign() == Sign::Minus)
             || self.mat[0] == 0.into())
-            && (((self.mat[0].clone() + self.mat[1].clone()).sign() == Sign::Minus)
-                ^ ((self.mat[2].clone() + self.mat[3].clone()).sign() == Sign::Minus)
+            && (((&self.mat[0] + &self.mat[1]).sign() == Sign::Minus)
+                ^ ((&self.mat[2] + &self.mat[3]).sign() == Sign::Minus)
                 || self.mat[0] == 0.into() && self.mat[1] == 0.into())
     }
 
     pub fn is_positive(&self) -> bool {
         self.mat[0] != 0.into()
-            && self.mat[0].clone() + self.mat[1].clone() != 0.into()
+            && &self.mat[0] + &self.mat[1] != 0.into()
             && !((self.mat[0].sign() == Sign::Minus) ^ (self.mat[2].sign() == Sign::Minus))
-            && !(((self.mat[0].clone() + self.mat[1].clone()).sign() == Sign::Minus)
-                ^ ((self.mat[2].clone() + self.mat[3].clone()).sign() == Sign::Minus))
+            && !(((&self.mat[0] + &self.mat[1]).sign() == Sign::Minus)
+                ^ ((&self.mat[2] + &self.mat[3]).sign() == Sign::Minus))
     }
 
     pub fn is_negative(&self) -> bool {
         self.mat[0] != 0.into()
-            && self.mat[0].clone() + self.mat[1].clone() != 0.into()
+            && &self.mat[0] + &self.mat[1] != 0.into()
             && (self.mat[0].sign() == Sign::Minus) ^ (self.mat[2].sign() == Sign::Minus)
-            && ((self.mat[0].clone() + self.mat[1].clone()).sign() == Sign::Minus)
-                ^ ((self.mat[2].clone() + self.mat[3].clone()).sign() == Sign::Minus)
-    }
-
-    pub fn intervals(&self) -> ((BigInt, BigInt), (BigInt, BigInt)) {
-        // (just removes the trailing zeros)
-        // (without which the numbers should be in fully reduced form)
-        fn prettify(mut a: BigInt, mut b: BigInt) -> (BigInt, BigInt) {
-            let g = a
-                .trailing_zeros()
-                .unwrap_or(0)
-                .min(b.trailing_zeros().unwrap_or(0));
-            if a < 0.into() && b < 0.into() {
-                a = -a;
-                b = -b;
-            }
-            // if a == 0.into() {
-            //     b = 1.into();
-            // }
-            // if b == 0.into() {
-            //     a = 1.into();
-            // }
-            (a >> g, b >> g)
+            && ((&self.mat[0] + &self.mat[1]).sign() == Sign::Minus)
+                ^ ((&self.mat[2] + &self.mat[3]).sign() == Sign::Minus)
+    }
+
+    // everything the lft could still evaluate to, for x in [1, oo]
+    // None if it's undefined somewhere in there, or exactly oo (neither is on the real line)
+    // if the denominator changes sign in there, the range wraps around through oo
+    // and the best a single interval can say is (-oo, oo)
+    pub fn interval(&self) -> Option<Interval> {
+        let [a, b, c, d] = &self.mat;
+        let zero = Coeff::from(0);
+        // z at x=oo (a/c, or b/d if the x terms vanish)
+        let (n_inf, d_inf) = if a.is_zero() && c.is_zero() {
+            (b.clone(), d.clone())
+        } else {
+            (a.clone(), c.clone())
+        };
+        // z at x=1
+        let (n_one, d_one) = (a + b, c + d);
+        if (n_inf.is_zero() && d_inf.is_zero()) || (n_one.is_zero() && d_one.is_zero()) {
+            return None;
         }
-        (
-            prettify(self.mat[0].clone(), self.mat[2].clone()),
-            prettify(
-                self.mat[0].clone() + self.mat[1].clone(),
-                self.mat[2].clone() + self.mat[3].clone(),
-            ),
-        )
+        if d_inf.is_zero() && d_one.is_zero() {
+            return None;
+        }
+        // the denominator's sign as x heads to oo, and just after x=1
+        let g_inf = if c.is_zero() { d.sign() } else { c.sign() };
+        let g_one = if d_one.is_zero() {
+            c.sign()
+        } else {
+            d_one.sign()
+        };
+        if g_inf != g_one {
+            return Some(Interval::everything());
+        }
+        // an infinite end's sign is the numerator's sign over the denominator's on the way there
+        let bound = |n: Coeff, den: Coeff, g: Sign| {
+            if den.is_zero() {
+                if (n < zero) == (g == Sign::Minus) {
+                    Bound::PosInf
+                } else {
+                    Bound::NegInf
+                }
+            } else {
+                Bound::Closed(BigRational::new(n.into(), den.into()))
+            }
+        };
+        Some(Interval::new(
+            bound(n_inf, d_inf, g_inf),
+            bound(n_one, d_one, g_one),
+        ))
+    }
+
+    // an lft taking [1, oo] onto the interval
+    // None for (-oo, oo), which isn't the image of anything we can ingest
+    pub fn from_interval(interval: &Interval) -> Option<Lft> {
+        let parts = |q: &BigRational| (Coeff::from(q.numer()), Coeff::from(q.denom()));
+        let mat = match (interval.lo.rational(), interval.hi.rational()) {
+            // p + (q - p)/x
+            (Some(p), Some(q)) => {
+                let ((pn, pd), (qn, qd)) = (parts(p), parts(q));
+                [&pn * &qd, &qn * &pd - &pn * &qd, &pd * &qd, 0.into()]
+            }
+            // p + x - 1
+            (Some(p), None) => {
+                let (pn, pd) = parts(p);
+                [pd.clone(), &pn - &pd, 0.into(), pd]
+            }
+            // q + 1 - x
+            (None, Some(q)) => {
+                let (qn, qd) = parts(q);
+                [-&qd, &qn + &qd, 0.into(), qd]
+            }
+            (None, None) => return None,
+        };
+        Some(Lft::create(mat))
+    }
+
+    // everything the lft could give for x in the interval
+    pub fn image(&self, interval: &Interval) -> Option<Interval> {
+        (self * &Lft::from_interval(interval)?).interval()
+    }
+
+    // f applied to both ends of the interval, if they agree
+    fn integer_part(&self, f: impl Fn(&BigRational) -> BigInt) -> Option<BigInt> {
+        let interval = self.interval()?;
+        let lo = f(interval.lo.rational()?);
+        let hi = f(interval.hi.rational()?);
+        (lo == hi).then_some(lo)
     }
 
     // rounds towards 0
     // returns the first term of this lft's continued fraction
     // (if x=oo and x=1 agree)
     pub fn trunc(&self) -> Option<BigInt> {
-        let ((n1, d1), (n2, d2)) = self.intervals();
-        let c1 = n1.checked_div(&d1)?;
-        let c2 = n2.checked_div(&d2)?;
-        (c1 == c2).then_some(c1)
+        self.integer_part(|q| q.trunc().to_integer())
     }
 
     // rounds towards -oo
     // returns t if we're both in [t, t+1) for int t
     pub fn floor(&self) -> Option<BigInt> {
-        let ((n1, d1), (n2, d2)) = self.intervals();
-        let i1 = n1.checked_div(&d1)?;
-        let i2 = n2.checked_div(&d2)?;
-        let a1 = n1 == i1.clone() * d1.clone();
-        let a2 = n2 == i2.clone() * d2.clone();
-        let f1 = if a1 || self.is_positive() { i1 } else { i1 - 1 };
-        let f2 = if a2 || self.is_positive() { i2 } else { i2 - 1 };
-        (f1 == f2).then_some(f1)
+        self.integer_part(|q| q.floor().to_integer())
     }
 
     // rounds towards +oo
     // returns t if we're both in (t-1, t] for int t
     pub fn ceil(&self) -> Option<BigInt> {
-        let ((n1, d1), (n2, d2)) = self.intervals();
-        let i1 = n1.checked_div(&d1)?;
-        let i2 = n2.checked_div(&d2)?;
-        let a1 = n1 == i1.clone() * d1.clone();
-        let a2 = n2 == i2.clone() * d2.clone();
-        let f1 = if a1 || self.is_negative() { i1 } else { i1 + 1 };
-        let f2 = if a2 || self.is_negative() { i2 } else { i2 + 1 };
-        (f1 == f2).then_some(f1)
+        self.integer_part(|q| q.ceil().to_integer())
     }
 
     // rounds half to even
@@ -137,30 +215,87 @@ impl Lft {
     // if we're at t+1/2, returns t if it's even, t+1 if it's not
     // for int t
     pub fn round(&self) -> Option<BigInt> {
-        let ((n1, d1), (n2, d2)) = self.intervals();
-        let i1 = n1.checked_div(&d1)?;
-        let i2 = n2.checked_div(&d2)?;
-        let h1 = (n1.clone() - i1.clone() * d1.clone()).into_parts().1;
-        let h2 = (n2.clone() - i2.clone() * d2.clone()).into_parts().1;
-        let offset = if self.is_positive() { 1 } else { -1 };
-        if i1 != i2 {
+        self.integer_part(|q| {
+            let floor = q.floor();
+            let twice_frac = (q - &floor) * BigInt::from(2);
+            let floor = floor.to_integer();
+            match twice_frac.cmp(&BigRational::from_integer(1.into())) {
+                Ordering::Less => floor,
+                Ordering::Greater => floor + 1,
+                Ordering::Equal if !floor.bit(0) => floor,
+                Ordering::Equal => floor + 1,
+            }
+        })
+    }
+}
+
+// lft algebra
+// lets us fold constant transformations together (or into arith units)
+// rather than spending a whole unit (and a cycle of delay) on each of them
+impl Lft {
+    // (we use 1/0 for oo, like everywhere else)
+    // the result is rationalized, so the denominator is never negative
+    // 0/0 means the lft is undefined at that point
+    pub fn eval(&self, num: &BigInt, den: &BigInt) -> (BigInt, BigInt) {
+        let (num, den) = (Coeff::from(num), Coeff::from(den));
+        let mut n = &self.mat[0] * &num + &self.mat[1] * &den;
+        let mut d = &self.mat[2] * &num + &self.mat[3] * &den;
+        let _ = crate::rationalize(&mut n, &mut d);
+        (n.into(), d.into())
+    }
+
+    // z at x=oo, i.e. a/c
+    pub fn eval_inf(&self) -> (BigInt, BigInt) {
+        self.eval(&1.into(), &0.into())
+    }
+
+    // the inverse is [d, -b, -c, a] (up to scaling)
+    // returns None if ad - bc = 0, since then the lft is constant and there's no way back
+    pub fn inverse(&self) -> Option<Lft> {
+        let det = &self.mat[0] * &self.mat[3] - &self.mat[1] * &self.mat[2];
+        if det == 0.into() {
             return None;
         }
-        let (d1, d2) = (d1.into_parts().1, d2.into_parts().1);
-        if h1.clone() * 2u32 < d1 && h2.clone() * 2u32 < d2 {
-            Some(i1)
-        } else if h1.clone() * 2u32 > d1 && h2.clone() * 2u32 > d2 {
-            Some(i1 + offset)
-        } else if h1.clone() * 2u32 == d1 && h2.clone() * 2u32 == d2 {
-            Some(if !i1.bit(0) { i1 } else { i1 + offset })
-        } else {
-            None
+        Some(Lft {
+            egest_enabled: self.egest_enabled,
+            ..Lft::create([
+                self.mat[3].clone(),
+                -&self.mat[1],
+                -&self.mat[2],
+                self.mat[0].clone(),
+            ])
+        })
+    }
+}
+
+// (l1 * l2)(x) = l1(l2(x))
+// i.e. plain matrix multiplication
+// the result is as egest-enabled as the outer lft
+impl std::ops::Mul<&Lft> for &Lft {
+    type Output = Lft;
+
+    fn mul(self, rhs: &Lft) -> Lft {
+        let [a, b, c, d] = &self.mat;
+        let [e, f, g, h] = &rhs.mat;
+        Lft {
+            egest_enabled: self.egest_enabled,
+            ..Lft::create([a * e + b * g, a * f + b * h, c * e + d * g, c * f + d * h])
         }
     }
 }
 
+impl std::ops::Mul for Lft {
+    type Output = Lft;
+
+    fn mul(self, rhs: Lft) -> Lft {
+        &self * &rhs
+    }
+}
+
 impl Unit for Lft {
     fn ingest_x(&mut self, x: Term) {
+        // (some lfts only ever ingest, e.g. ones keeping track of where a stream has got to)
+        self.reducer.tick(&mut self.mat);
         match x {
             Term::Empty => {}
             Term::Ord => {
@@ -175,16 +310,16 @@ impl Unit for Lft {
             Term::DRec => {
                 self.mat.swap(0, 1);
                 self.mat.swap(2, 3);
-                self.mat[0] += self.mat[1].clone();
-                self.mat[2] += self.mat[3].clone();
+                coeff::add_idx(&mut self.mat, 0, 1);
+                coeff::add_idx(&mut self.mat, 2, 3);
             }
             Term::Rec => {
                 self.mat.swap(0, 1);
                 self.mat.swap(2, 3);
             }
             Term::Neg => {
-                self.mat[0] = -self.mat[0].clone();
-                self.mat[2] = -self.mat[2].clone();
+                self.mat[0] = -&self.mat[0];
+                self.mat[2] = -&self.mat[2];
             }
             Term::Inf => {
                 self.mat[1] = self.mat[0].clone();
@@ -201,18 +336,21 @@ impl Unit for Lft {
     }
 
     fn egest_z(&mut self) -> Term {
+        self.reducer.tick(&mut self.mat);
+
         if !self.egest_enabled {
             return Term::Empty;
         }
 
         if self.is_undefined() {
+            trace!("lft: undefined");
             return Term::Undefined;
         }
         if self.is_inf() {
             // we like distinguishing between -oo and +oo
             if self.mat[0] < 0.into() || self.mat[1] < 0.into() {
-                self.mat[0] = -self.mat[0].clone();
-                self.mat[1] = -self.mat[1].clone();
+                self.mat[0] = -&self.mat[0];
+                self.mat[1] = -&self.mat[1];
                 return Term::Neg;
             }
             return Term::Inf;
@@ -220,9 +358,9 @@ impl Unit for Lft {
 
         // n0 is x=oo, n1 is x=1
         let mut n0 = self.mat[0].clone();
-        let mut n1 = self.mat[0].clone() + self.mat[1].clone();
+        let mut n1 = &self.mat[0] + &self.mat[1];
         let mut d0 = self.mat[2].clone();
-        let mut d1 = self.mat[2].clone() + self.mat[3].clone();
+        let mut d1 = &self.mat[2] + &self.mat[3];
         let _ = crate::rationalize(&mut n0, &mut d0);
         let _ = crate::rationalize(&mut n1, &mut d1);
         let nums_agreed = (n0 < 0.into()) == (n1 < 0.into());
@@ -233,10 +371,10 @@ impl Unit for Lft {
 
         if well_defined {
             if n0.sign() == Sign::Minus && n1.sign() == Sign::Minus {
-                self.mat[0] = -self.mat[0].clone();
-                self.mat[1] = -self.mat[1].clone();
+                self.mat[0] = -&self.mat[0];
+                self.mat[1] = -&self.mat[1];
                 return Term::Neg;
-            } else if (n0.clone() >> 1) >= d0 && (n1.clone() >> 1) >= d1 {
+            } else if (&n0 >> 1) >= d0 && (&n1 >> 1) >= d1 {
                 if !self.mat[0].bit(0) && !self.mat[1].bit(0) {
                     self.mat[0] >>= 1;
                     self.mat[1] >>= 1;
@@ -245,9 +383,9 @@ impl Unit for Lft {
                     self.mat[3] <<= 1;
                 }
                 return Term::Ord;
-            } else if n0 >= d0 && n1 >= d1 && (n0.clone() >> 1) < d0 && (n1.clone() >> 1) < d1 {
-                self.mat[0] -= self.mat[2].clone();
-                self.mat[1] -= self.mat[3].clone();
+            } else if n0 >= d0 && n1 >= d1 && (&n0 >> 1) < d0 && (&n1 >> 1) < d1 {
+                coeff::sub_idx(&mut self.mat, 0, 2);
+                coeff::sub_idx(&mut self.mat, 1, 3);
                 self.mat.swap(0, 2);
                 self.mat.swap(1, 3);
                 return Term::DRec;
@@ -260,4 +398,108 @@ impl Unit for Lft {
 
         Term::Empty
     }
+
+    fn max_bits(&self) -> u64 {
+        reduce::max_bits(&self.mat)
+    }
+
+    fn matrix_bits(&self) -> Vec<(&'static str, u64)> {
+        vec![("mat", self.max_bits())]
+    }
+
+    fn reduce_stats(&self) -> ReduceStats {
+        self.reducer.stats
+    }
+
+    fn undefined_reason(&self) -> UndefinedReason {
+        UndefinedReason::Indeterminate
+    }
+
+    fn pending(&self) -> Option<Interval> {
+        self.interval()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // points on the projective line, oo and all
+    fn points() -> Vec<(BigInt, BigInt)> {
+        [(0, 1), (1, 1), (-3, 1), (1, 2), (-5, 7), (22, 7), (1, 0)]
+            .into_iter()
+            .map(|(n, d)| (n.into(), d.into()))
+            .collect()
+    }
+
+    // n1/d1 = n2/d2, without caring about common factors
+    // (0/0 only equals itself)
+    fn same((n1, d1): &(BigInt, BigInt), (n2, d2): &(BigInt, BigInt)) -> bool {
+        let undefined =
+            |n: &BigInt, d: &BigInt| n.sign() == Sign::NoSign && d.sign() == Sign::NoSign;
+        undefined(n1, d1) == undefined(n2, d2) && n1 * d2 == n2 * d1
+    }
+
+    fn lft(a: i64, b: i64, c: i64, d: i64) -> Lft {
+        Lft::create([a.into(), b.into(), c.into(), d.into()])
+    }
+
+    #[test]
+    fn eval_is_rationalized() {
+        let f = lft(2, 1, 1, 3);
+        assert_eq!(f.eval(&1.into(), &2.into()), (4.into(), 7.into()));
+        // (the sign moves up to the numerator)
+        assert_eq!(f.eval(&(-1).into(), &1.into()), ((-1).into(), 2.into()));
+        assert!(same(
+            &f.eval(&(-3).into(), &1.into()),
+            &(1.into(), 0.into())
+        ));
+        assert_eq!(f.eval_inf(), (2.into(), 1.into()));
+        assert_eq!(lft(1, 0, 0, 1).eval_inf(), (1.into(), 0.into()));
+    }
+
+    #[test]
+    fn mul_composes() {
+        let f = lft(2, 1, 1, 3);
+        let g = lft(1, -2, 3, 4);
+        for q in points() {
+            let (n, d) = g.eval(&q.0, &q.1);
+            assert!(same(&(&f * &g).eval(&q.0, &q.1), &f.eval(&n, &d)));
+        }
+        // (and it's the outer lft that decides whether we egest)
+        let locked = Lft {
+            egest_enabled: false,
+            ..f.clone()
+        };
+        assert!(!(&locked * &g).egest_enabled);
+        assert!((g * locked).egest_enabled);
+    }
+
+    #[test]
+    fn inverse_undoes() {
+        for f in [
+            lft(2, 1, 1, 3),
+            lft(1, -2, 3, 4),
+            Lft::reciprocal(),
+            Lft::affine(-3, 5),
+        ] {
+            let inverse = f.inverse().unwrap();
+            for q in points() {
+                let (n, d) = f.eval(&q.0, &q.1);
+                assert!(same(&inverse.eval(&n, &d), &q));
+            }
+        }
+    }
+
+    #[test]
+    fn degenerate() {
+        // (2x + 4) / (x + 2) is 2, except at -2 where it's 0/0
+        let f = lft(2, 4, 1, 2);
+        assert!(f.inverse().is_none());
+        assert!((&f * &lft(1, -2, 3, 4)).inverse().is_none());
+        for q in points() {
+            assert!(same(&f.eval(&q.0, &q.1), &(2.into(), 1.into())));
+        }
+        assert_eq!(f.eval(&(-2).into(), &1.into()), (0.into(), 0.into()));
+    }
 }
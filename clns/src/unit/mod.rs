@@ -12,7 +12,7 @@
 
 use std::fmt::Debug;
 
-use crate::Term;
+use crate::{Term, interval::Interval, reduce::ReduceStats};
 
 pub mod arith;
 pub mod cfrac;
@@ -29,11 +29,15 @@ use int::Modulo;
 use lft::Lft;
 use sqrt::Sqrt;
 
+use super::workgroup::Slot;
+use super::workgroup::Workgroup;
+use super::workgroup::embed::EmbeddableWorkgroup;
 use super::workgroup::exp::ExpTaylor;
 use super::workgroup::log2::Log2;
 
 #[enum_dispatch::enum_dispatch]
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum UnitUnion {
     Arith,
     CLogs,
@@ -44,6 +48,100 @@ pub enum UnitUnion {
     Sqrt,
     ExpTaylor,
     Log2,
+    EmbeddableWorkgroup,
+}
+
+// how a unit kind uses one of its input slots
+// (used to sanity check workgroup wiring)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Input {
+    // has to be fed (wired up or hand-fed)
+    Required,
+    // can be fed, but works without (e.g. an unfed lft is a constant)
+    Optional,
+    // never looked at
+    Unused,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Arity {
+    pub x: Input,
+    pub y: Input,
+}
+
+impl Arity {
+    pub const NULLARY: Self = Self {
+        x: Input::Unused,
+        y: Input::Unused,
+    };
+    pub const UNARY: Self = Self {
+        x: Input::Required,
+        y: Input::Unused,
+    };
+    pub const BINARY: Self = Self {
+        x: Input::Required,
+        y: Input::Required,
+    };
+}
+
+// why a unit started egesting undefined (see Workgroup::undefined_origin)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum UndefinedReason {
+    // it was handed an undefined input first
+    // (the workgroup fills this one in, but a unit can say so too if it can tell)
+    Input(Slot),
+    // 0/0, oo-oo, 0*oo and friends
+    Indeterminate,
+    SqrtOfNegative,
+    // x % 0, or oo % y
+    BadModulo,
+    LogOfNonpositive,
+    // defined, but not something we know how to compute yet (e.g. log2 of x <= 1/2)
+    Unsupported,
+    // the source itself said so
+    Source,
+    Unknown,
+}
+
+impl UnitUnion {
+    pub fn arity(&self) -> Arity {
+        match self {
+            Self::Arith(_) | Self::Compare(_) | Self::Modulo(_) => Arity::BINARY,
+            Self::Sqrt(_) | Self::ExpTaylor(_) | Self::Log2(_) => Arity::UNARY,
+            Self::CLogs(_) | Self::FromCFrac(_) => Arity::NULLARY,
+            Self::Lft(_) => Arity {
+                x: Input::Optional,
+                y: Input::Unused,
+            },
+            Self::EmbeddableWorkgroup(wg) => wg.arity(),
+        }
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Arith(_) => "arith",
+            Self::CLogs(_) => "clogs",
+            Self::Compare(_) => "compare",
+            Self::FromCFrac(_) => "from_cfrac",
+            Self::Lft(_) => "lft",
+            Self::Modulo(_) => "modulo",
+            Self::Sqrt(_) => "sqrt",
+            Self::ExpTaylor(_) => "exp_taylor",
+            Self::Log2(_) => "log2",
+            Self::EmbeddableWorkgroup(_) => "embedded",
+        }
+    }
+
+    // the workgroup a unit is built out of, if it is
+    pub fn inner_workgroup(&self) -> Option<&Workgroup> {
+        match self {
+            Self::ExpTaylor(exp) => Some(exp.inner()),
+            Self::Log2(log2) => Some(log2.inner()),
+            Self::EmbeddableWorkgroup(wg) => Some(wg.inner()),
+            _ => None,
+        }
+    }
 }
 
 #[enum_dispatch::enum_dispatch(UnitUnion)]
@@ -51,17 +149,112 @@ pub trait Unit {
     fn ingest_x(&mut self, x: Term);
     fn ingest_y(&mut self, y: Term);
     fn egest_z(&mut self) -> Term;
+
+    // the biggest coefficient anywhere in the unit's state, in bits
+    // (for keeping an eye on growth; 0 if there's nothing to grow)
+    fn max_bits(&self) -> u64 {
+        0
+    }
+
+    // the same, but for each of the unit's own matrices, named after where it's kept
+    // (an inner workgroup's units aren't included, they have their own)
+    fn matrix_bits(&self) -> Vec<(&'static str, u64)> {
+        vec![]
+    }
+
+    // how the unit's reducers have been getting on (see reduce.rs)
+    // summed over all of them if there's more than one
+    fn reduce_stats(&self) -> ReduceStats {
+        ReduceStats::default()
+    }
+
+    // why the last egest_z gave Undefined, going by the unit's state right after it
+    // (only asked the first time, and only if no undefined input came in beforehand)
+    fn undefined_reason(&self) -> UndefinedReason {
+        UndefinedReason::Unknown
+    }
+
+    // where the output the unit hasn't egested yet has to lie (in the same coordinates as
+    // its next term, so a reader that's ingested everything so far would map it through that)
+    // None if the unit can't say
+    fn pending(&self) -> Option<Interval> {
+        None
+    }
 }
 
 // you can put anything that'll generate a clog stream into here
 // unload a vector, a recurring sequence, whatever
-pub struct CLogs {
-    pub terms: Box<dyn FnMut() -> Term>,
+// (closures work too, but they're opaque, so prefer the other sources when you can)
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum CLogSource {
+    // the terms in order, then Empty forever
+    Terms {
+        terms: Vec<Term>,
+        index: usize,
+    },
+    // the same term forever (e.g. oo)
+    Repeat(Term),
+    // (closures can't be serialized; trying to is an error)
+    #[cfg_attr(feature = "serde", serde(skip))]
+    Fn(Box<dyn CLogFn>),
+}
+
+// a clog-generating closure that can be cloned (so that workgroups can be forked)
+// any FnMut() -> Term + Clone + Send gets this for free
+// (closures are Clone/Send whenever everything they capture is)
+// Send so that units can be cycled on other threads
+pub trait CLogFn: FnMut() -> Term + Send {
+    fn clone_box(&self) -> Box<dyn CLogFn>;
+}
+
+impl<F: FnMut() -> Term + Clone + Send + 'static> CLogFn for F {
+    fn clone_box(&self) -> Box<dyn CLogFn> {
+        Box::new(self.clone())
+    }
+}
+
+impl Clone for Box<dyn CLogFn> {
+    fn clone(&self) -> Self {
+        (**self).clone_box()
+    }
 }
 
-impl Debug for CLogs {
+impl Debug for CLogSource {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "CLogs: (internal iter cannot be inspected)")
+        match self {
+            Self::Terms { terms, index } => {
+                write!(f, "Terms {{ terms: {:?}, index: {} }}", terms, index)
+            }
+            Self::Repeat(term) => write!(f, "Repeat({:?})", term),
+            Self::Fn(_) => write!(f, "Fn (internal iter cannot be inspected)"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CLogs {
+    pub source: CLogSource,
+}
+
+impl CLogs {
+    pub fn from_terms(terms: Vec<Term>) -> Self {
+        Self {
+            source: CLogSource::Terms { terms, index: 0 },
+        }
+    }
+
+    pub fn repeat(term: Term) -> Self {
+        Self {
+            source: CLogSource::Repeat(term),
+        }
+    }
+
+    pub fn from_fn(f: impl FnMut() -> Term + Clone + Send + 'static) -> Self {
+        Self {
+            source: CLogSource::Fn(Box::new(f)),
+        }
     }
 }
 
@@ -69,6 +262,18 @@ impl Unit for CLogs {
     fn ingest_x(&mut self, _: Term) {}
     fn ingest_y(&mut self, _: Term) {}
     fn egest_z(&mut self) -> Term {
-        (self.terms)()
+        match &mut self.source {
+            CLogSource::Terms { terms, index } => {
+                let term = terms.get(*index).copied().unwrap_or(Term::Empty);
+                *index = (*index + 1).min(terms.len());
+                term
+            }
+            CLogSource::Repeat(term) => *term,
+            CLogSource::Fn(f) => f(),
+        }
+    }
+
+    fn undefined_reason(&self) -> UndefinedReason {
+        UndefinedReason::Source
     }
 }
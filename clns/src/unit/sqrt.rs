@@ -1,12 +1,20 @@
-use num_bigint::{BigInt, Sign};
+use num_bigint::Sign;
 
-use crate::{Term, unit::Unit};
+use crate::{
+    Term,
+    coeff::{self, Coeff},
+    reduce::{self, ReduceStats, Reducer},
+    unit::{UndefinedReason, Unit},
+    workgroup::Slot,
+};
 
 pub const SQRT_SPECULATE: bool = true;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Sqrt {
-    mat: [BigInt; 8],
+    mat: [Coeff; 8],
+    pub reducer: Reducer,
 }
 
 impl Sqrt {
@@ -25,6 +33,7 @@ impl Sqrt {
                 1.into(),
                 0.into(),
             ],
+            reducer: Reducer::default(),
         }
     }
 }
@@ -33,10 +42,7 @@ impl Sqrt {
 impl Sqrt {
     fn feedback_ord(&mut self) -> Term {
         // egest
-        if self.mat[0].clone() % 2 == 0.into()
-            && self.mat[1].clone() % 2 == 0.into()
-            && self.mat[2].clone() % 2 == 0.into()
-            && self.mat[3].clone() % 2 == 0.into()
+        if !self.mat[0].bit(0) && !self.mat[1].bit(0) && !self.mat[2].bit(0) && !self.mat[3].bit(0)
         {
             self.mat[0] >>= 1;
             self.mat[1] >>= 1;
@@ -49,10 +55,7 @@ impl Sqrt {
             self.mat[7] <<= 1;
         }
         // ingest
-        if self.mat[1].clone() % 2 == 0.into()
-            && self.mat[3].clone() % 2 == 0.into()
-            && self.mat[5].clone() % 2 == 0.into()
-            && self.mat[7].clone() % 2 == 0.into()
+        if !self.mat[1].bit(0) && !self.mat[3].bit(0) && !self.mat[5].bit(0) && !self.mat[7].bit(0)
         {
             self.mat[1] >>= 1;
             self.mat[3] >>= 1;
@@ -68,10 +71,10 @@ impl Sqrt {
     }
     fn feedback_drec(&mut self) -> Term {
         // egest
-        self.mat[0] -= self.mat[4].clone();
-        self.mat[1] -= self.mat[5].clone();
-        self.mat[2] -= self.mat[6].clone();
-        self.mat[3] -= self.mat[7].clone();
+        coeff::sub_idx(&mut self.mat, 0, 4);
+        coeff::sub_idx(&mut self.mat, 1, 5);
+        coeff::sub_idx(&mut self.mat, 2, 6);
+        coeff::sub_idx(&mut self.mat, 3, 7);
         self.mat.swap(0, 4);
         self.mat.swap(1, 5);
         self.mat.swap(2, 6);
@@ -81,10 +84,10 @@ impl Sqrt {
         self.mat.swap(2, 3);
         self.mat.swap(4, 5);
         self.mat.swap(6, 7);
-        self.mat[0] += self.mat[1].clone();
-        self.mat[2] += self.mat[3].clone();
-        self.mat[4] += self.mat[5].clone();
-        self.mat[6] += self.mat[7].clone();
+        coeff::add_idx(&mut self.mat, 0, 1);
+        coeff::add_idx(&mut self.mat, 2, 3);
+        coeff::add_idx(&mut self.mat, 4, 5);
+        coeff::add_idx(&mut self.mat, 6, 7);
         Term::DRec
     }
     fn feedback_rec(&mut self) -> Term {
@@ -102,21 +105,22 @@ impl Sqrt {
     }
     fn feedback_neg(&mut self) -> Term {
         // egest
-        // self.mat[0] = -self.mat[0].clone();
-        self.mat[1] = -self.mat[1].clone();
-        // self.mat[2] = -self.mat[2].clone();
-        self.mat[3] = -self.mat[3].clone();
+        // self.mat[0] = -&self.mat[0];
+        self.mat[1] = -&self.mat[1];
+        // self.mat[2] = -&self.mat[2];
+        self.mat[3] = -&self.mat[3];
         // ingest
-        // self.mat[0] = -self.mat[0].clone();
-        // self.mat[2] = -self.mat[2].clone();
-        self.mat[4] = -self.mat[4].clone();
-        self.mat[6] = -self.mat[6].clone();
+        // self.mat[0] = -&self.mat[0];
+        // self.mat[2] = -&self.mat[2];
+        self.mat[4] = -&self.mat[4];
+        self.mat[6] = -&self.mat[6];
         Term::Neg
     }
 }
 
 impl Unit for Sqrt {
     fn ingest_x(&mut self, x: Term) {
+        self.reducer.tick(&mut self.mat);
         // println!("ingesting {:?}", x);
         match x {
             Term::Empty => {}
@@ -142,10 +146,10 @@ impl Unit for Sqrt {
                 self.mat.swap(1, 3);
                 self.mat.swap(4, 6);
                 self.mat.swap(5, 7);
-                self.mat[0] += self.mat[2].clone();
-                self.mat[1] += self.mat[3].clone();
-                self.mat[4] += self.mat[6].clone();
-                self.mat[5] += self.mat[7].clone();
+                coeff::add_idx(&mut self.mat, 0, 2);
+                coeff::add_idx(&mut self.mat, 1, 3);
+                coeff::add_idx(&mut self.mat, 4, 6);
+                coeff::add_idx(&mut self.mat, 5, 7);
             }
             Term::Rec => {
                 self.mat.swap(0, 2);
@@ -154,10 +158,10 @@ impl Unit for Sqrt {
                 self.mat.swap(5, 7);
             }
             Term::Neg => {
-                self.mat[0] = -self.mat[0].clone();
-                self.mat[1] = -self.mat[1].clone();
-                self.mat[4] = -self.mat[4].clone();
-                self.mat[5] = -self.mat[5].clone();
+                self.mat[0] = -&self.mat[0];
+                self.mat[1] = -&self.mat[1];
+                self.mat[4] = -&self.mat[4];
+                self.mat[5] = -&self.mat[5];
             }
             Term::Inf => {
                 self.mat[2] = self.mat[0].clone();
@@ -184,6 +188,8 @@ impl Unit for Sqrt {
     fn ingest_y(&mut self, _y: Term) {}
 
     fn egest_z(&mut self) -> Term {
+        self.reducer.tick(&mut self.mat);
+
         // if there's no y in the denominator,
         // whether x=1 or x=oo
         // then we've hit oo
@@ -191,6 +197,7 @@ impl Unit for Sqrt {
             // added to catch the case that the input is -oo
             // since that results in a state we don't catch as "undefined"
             if self.mat.iter().all(|i| *i <= 0.into()) {
+                trace!("sqrt: input is -oo, undefined");
                 return Term::Undefined;
             }
             return Term::Inf;
@@ -200,12 +207,9 @@ impl Unit for Sqrt {
         // the derivative is (ad - bc)/(cy + d)^2
         // since the roots (if they exist) are always on both sides of the asymptote,
         // the expression can't be defined if its derivative is always positive
-        let defined_oo =
-            self.mat[0].clone() * self.mat[5].clone() <= self.mat[1].clone() * self.mat[4].clone();
-        let defined_1 = (self.mat[0].clone() + self.mat[2].clone())
-            * (self.mat[5].clone() + self.mat[7].clone())
-            <= (self.mat[1].clone() + self.mat[3].clone())
-                * (self.mat[4].clone() + self.mat[6].clone());
+        let m = &self.mat;
+        let defined_oo = &m[0] * &m[5] <= &m[1] * &m[4];
+        let defined_1 = (&m[0] + &m[2]) * (&m[5] + &m[7]) <= (&m[1] + &m[3]) * (&m[4] + &m[6]);
 
         // normally we don't find ourselves in a situation where x straddles 0
         // so this is probably fine to be an or
@@ -220,6 +224,7 @@ impl Unit for Sqrt {
             // in practice i don't think this is a problem
             // because we tend to presume that inputs are positive, rather than negative
             // we prefer to speculatively egest / over -
+            trace!("sqrt: input might be negative, undefined");
             return Term::Undefined;
         }
 
@@ -241,20 +246,20 @@ impl Unit for Sqrt {
             // rec_spec: bool,
             // ord_sing: bool,
         }
-        fn decide_lft(a: BigInt, b: BigInt, c: BigInt, d: BigInt) -> LFTDecision {
+        fn decide_lft(a: &Coeff, b: &Coeff, c: &Coeff, d: &Coeff) -> LFTDecision {
             let mut decision = LFTDecision::default();
             // asymptote :D
-            let mut a_num = -d.clone();
+            let mut a_num = -d;
             let mut a_den = c.clone();
             // if the asymptote doesn't exist, then we must be either a decreasing or constant line
             // since if c=0, we have ad <= 0
-            let a_sign = (c != 0.into()).then_some(crate::rationalize(&mut a_num, &mut a_den));
+            let a_sign = (!c.is_zero()).then_some(crate::rationalize(&mut a_num, &mut a_den));
             // now we've gotta sample y at some points :P
-            let mut y2_num = 2 * a.clone() + b.clone();
-            let mut y2_den = 2 * c.clone() + d.clone();
+            let mut y2_num = 2 * a + b;
+            let mut y2_den = 2 * c + d;
             let _ = crate::rationalize(&mut y2_num, &mut y2_den);
-            let mut y1_num = a.clone() + b.clone();
-            let mut y1_den = c.clone() + d.clone();
+            let mut y1_num = a + b;
+            let mut y1_den = c + d;
             let _ = crate::rationalize(&mut y1_num, &mut y1_den);
             let mut y0_num = b.clone();
             let mut y0_den = d.clone();
@@ -263,15 +268,12 @@ impl Unit for Sqrt {
             // println!("({}y + {})/({}y + {})", a, b, c, d);
 
             // y in [2, inf]
-            if a_num >= 2 * a_den.clone() || y2_num >= 2 * y2_den.clone() {
+            if a_num >= 2 * &a_den || y2_num >= 2 * &y2_den {
                 decision.ord = true;
                 // decision.ord_sing = true;
             }
             // y in [1, 2)
-            if a_num < 2 * a_den.clone()
-                && y2_num < 2 * y2_den.clone()
-                && (a_num >= a_den || y1_num >= y1_den)
-            {
+            if a_num < 2 * &a_den && y2_num < 2 * &y2_den && (a_num >= a_den || y1_num >= y1_den) {
                 decision.drec = true;
             }
             // y in [0, 1)
@@ -284,11 +286,11 @@ impl Unit for Sqrt {
             }
 
             // speculative stuff
-            let mut y4_num = 4 * a.clone() + b.clone();
-            let mut y4_den = 4 * c.clone() + d.clone();
+            let mut y4_num = 4 * a + b;
+            let mut y4_den = 4 * c + d;
             let _ = crate::rationalize(&mut y4_num, &mut y4_den);
-            let mut yh_num = a.clone() + 2 * b.clone();
-            let mut yh_den = c.clone() + 2 * d.clone();
+            let mut yh_num = a + 2 * b;
+            let mut yh_den = c + 2 * d;
             let _ = crate::rationalize(&mut yh_num, &mut yh_den);
             // let mut ym2_num = 2 * -a.clone() + b.clone();
             // let mut ym2_den = 2 * -c.clone() + d.clone();
@@ -297,16 +299,13 @@ impl Unit for Sqrt {
             // let mut ym1_den = -c.clone() + d.clone();
             // let ym1_sign = crate::rationalize(&mut ym1_num, &mut ym1_den);
             // y in (1, 4)
-            if a_num < 4 * a_den.clone()
-                && y4_num < 4 * y4_den.clone()
-                && (a_num >= a_den || y1_num >= y1_den)
-            {
+            if a_num < 4 * &a_den && y4_num < 4 * &y4_den && (a_num >= a_den || y1_num >= y1_den) {
                 decision.ord_spec = true;
             }
             // y in (1/2, 2)
-            if a_num < 2 * a_den.clone()
-                && y2_num < 2 * y2_den.clone()
-                && (2 * a_num.clone() >= a_den.clone() || 2 * yh_num.clone() >= yh_den)
+            if a_num < 2 * &a_den
+                && y2_num < 2 * &y2_den
+                && (2 * &a_num >= a_den || 2 * &yh_num >= yh_den)
             {
                 decision.drec_spec = true;
             }
@@ -320,24 +319,19 @@ impl Unit for Sqrt {
             //     decision.rec_spec = true;
             // }
             // // y in [-inf, 2]
-            // if (a_sign.is_some() && -a_num >= 2 * a_den.clone()) || -ym2_num >= 2 * ym2_den.clone() {
+            // if (a_sign.is_some() && -a_num >= 2 * &a_den) || -ym2_num >= 2 * &ym2_den {
             //     decision.ord_sing = true;
             // }
 
             decision
         }
 
-        let decisions_oo = decide_lft(
-            self.mat[0].clone(),
-            self.mat[1].clone(),
-            self.mat[4].clone(),
-            self.mat[5].clone(),
-        );
+        let decisions_oo = decide_lft(&m[0], &m[1], &m[4], &m[5]);
         let decisions_1 = decide_lft(
-            self.mat[0].clone() + self.mat[2].clone(),
-            self.mat[1].clone() + self.mat[3].clone(),
-            self.mat[4].clone() + self.mat[6].clone(),
-            self.mat[5].clone() + self.mat[7].clone(),
+            &(&m[0] + &m[2]),
+            &(&m[1] + &m[3]),
+            &(&m[4] + &m[6]),
+            &(&m[5] + &m[7]),
         );
 
         if decisions_oo.ord && decisions_1.ord {
@@ -354,9 +348,11 @@ impl Unit for Sqrt {
         }
         if SQRT_SPECULATE {
             if decisions_oo.ord_spec && decisions_1.ord_spec {
+                trace!("sqrt: speculating 1 (z in (1, 4))");
                 return self.feedback_ord();
             }
             if decisions_oo.drec_spec && decisions_1.drec_spec {
+                trace!("sqrt: speculating 0 (z in (1/2, 2))");
                 return self.feedback_drec();
             }
             // if decisions_oo.rec_spec && decisions_1.rec_spec {
@@ -370,4 +366,25 @@ impl Unit for Sqrt {
 
         Term::Empty
     }
+
+    fn max_bits(&self) -> u64 {
+        reduce::max_bits(&self.mat)
+    }
+
+    fn matrix_bits(&self) -> Vec<(&'static str, u64)> {
+        vec![("mat", self.max_bits())]
+    }
+
+    fn reduce_stats(&self) -> ReduceStats {
+        self.reducer.stats
+    }
+
+    // ingesting undefined zeroes the whole matrix (and nothing else does)
+    fn undefined_reason(&self) -> UndefinedReason {
+        if self.mat.iter().all(Coeff::is_zero) {
+            UndefinedReason::Input(Slot::X)
+        } else {
+            UndefinedReason::SqrtOfNegative
+        }
+    }
 }
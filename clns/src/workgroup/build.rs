@@ -0,0 +1,196 @@
+use std::collections::HashMap;
+
+use crate::{
+    Error, Term,
+    coeff::Coeff,
+    reduce,
+    unit::{
+        CLogs, arith::Arith, cfrac, cfrac::FromCFrac, cmp::Compare, int::Modulo, lft::Lft,
+        sqrt::Sqrt,
+    },
+    workgroup::{UnitConcrete, UnitId, Workgroup, exp::ExpTaylor, log2::Log2},
+};
+
+// a hash-consing layer over a workgroup
+// structurally identical subexpressions (same kind of unit, same parameters, same inputs)
+// get built once and share a UnitId
+// so e.g. x*x + x only has one x in it, and `pi pi *` only has one pi
+//
+// sharing is decided when a unit gets added, off of its parameters
+// (the matrix for arith/lft, the source for sources)
+// so hand it freshly created units, not ones that have already been run
+#[derive(Debug, Clone)]
+pub struct Builder {
+    wg: Workgroup,
+    shared: HashMap<Shape, UnitId>,
+}
+
+// everything that determines what a unit computes
+// (matrices are divided through by their content, since scaling doesn't change anything)
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Shape {
+    Arith([Coeff; 8], UnitId, UnitId),
+    Lft([Coeff; 4], bool, Option<UnitId>),
+    Sqrt(UnitId),
+    Modulo(UnitId, UnitId),
+    Compare(UnitId, UnitId),
+    ExpTaylor(UnitId),
+    Log2(UnitId),
+    E,
+    Pi,
+    SimpleCFrac(Vec<i64>),
+    CLogTerms(Vec<Term>),
+    CLogRepeat(Term),
+}
+
+impl Default for Builder {
+    fn default() -> Self {
+        Self::create()
+    }
+}
+
+impl Builder {
+    pub fn create() -> Self {
+        Self {
+            wg: Workgroup::create(),
+            shared: HashMap::new(),
+        }
+    }
+
+    pub fn workgroup(&self) -> &Workgroup {
+        &self.wg
+    }
+
+    pub fn into_workgroup(self) -> Workgroup {
+        self.wg
+    }
+
+    // into_workgroup, but only if the graph validates
+    pub fn try_into_workgroup(self) -> Result<Workgroup, Error> {
+        self.wg.validate()?;
+        Ok(self.wg)
+    }
+
+    fn share(&mut self, shape: Shape, make: impl FnOnce(&mut Workgroup) -> UnitId) -> UnitId {
+        if let Some(&id) = self.shared.get(&shape) {
+            return id;
+        }
+        let id = make(&mut self.wg);
+        self.shared.insert(shape, id);
+        id
+    }
+
+    // never shared (for units we can't see into, e.g. closure-backed sources)
+    pub fn add_unique(&mut self, unit: UnitConcrete) -> UnitId {
+        self.wg.add_unit(unit)
+    }
+
+    // add_unique, but refuses inputs the workgroup doesn't know about
+    pub fn try_add_unique(&mut self, unit: UnitConcrete) -> Result<UnitId, Error> {
+        self.wg.try_add_unit(unit)
+    }
+
+    pub fn arith(&mut self, unit: Arith, x: UnitId, y: UnitId) -> UnitId {
+        let mut mat = unit.mat.clone();
+        reduce::reduce_content(&mut mat);
+        // if swapping x and y doesn't change anything (add, mul, ...)
+        // then the order we got them in doesn't matter either
+        let (kx, ky) = if mat[1] == mat[2] && mat[5] == mat[6] {
+            (x.min(y), x.max(y))
+        } else {
+            (x, y)
+        };
+        self.share(Shape::Arith(mat, kx, ky), |wg| wg.add_arith(unit, x, y))
+    }
+
+    pub fn lft(&mut self, unit: Lft, x: Option<UnitId>) -> UnitId {
+        let mut mat = unit.mat.clone();
+        reduce::reduce_content(&mut mat);
+        self.share(Shape::Lft(mat, unit.egest_enabled, x), |wg| {
+            wg.add_lft(unit, x)
+        })
+    }
+
+    pub fn sqrt(&mut self, x: UnitId) -> UnitId {
+        self.share(Shape::Sqrt(x), |wg| wg.add_sqrt(Sqrt::create(), x))
+    }
+
+    pub fn modulo(&mut self, x: UnitId, y: UnitId) -> UnitId {
+        self.share(Shape::Modulo(x, y), |wg| {
+            wg.add_modulo(Modulo::create(), x, y)
+        })
+    }
+
+    pub fn compare(&mut self, x: UnitId, y: UnitId) -> UnitId {
+        self.share(Shape::Compare(x, y), |wg| {
+            wg.add_compare(Compare::create(), x, y)
+        })
+    }
+
+    pub fn exp_taylor(&mut self, x: UnitId) -> UnitId {
+        self.share(Shape::ExpTaylor(x), |wg| {
+            wg.add_exp_taylor(ExpTaylor::create(), x)
+        })
+    }
+
+    pub fn log2(&mut self, x: UnitId) -> UnitId {
+        self.share(Shape::Log2(x), |wg| wg.add_log2(Log2::create(), x))
+    }
+
+    // sources
+
+    pub fn e(&mut self) -> UnitId {
+        self.share(Shape::E, |wg| wg.add_from_cfrac(cfrac::consts::e()))
+    }
+
+    pub fn pi(&mut self) -> UnitId {
+        self.share(Shape::Pi, |wg| wg.add_from_cfrac(cfrac::consts::pi()))
+    }
+
+    pub fn simple_cfrac(&mut self, terms: Vec<i64>) -> UnitId {
+        self.share(Shape::SimpleCFrac(terms.clone()), |wg| {
+            wg.add_from_cfrac(FromCFrac::simple(terms))
+        })
+    }
+
+    pub fn clog_terms(&mut self, terms: Vec<Term>) -> UnitId {
+        self.share(Shape::CLogTerms(terms.clone()), |wg| {
+            wg.add_clogs(CLogs::from_terms(terms))
+        })
+    }
+
+    pub fn clog_repeat(&mut self, term: Term) -> UnitId {
+        self.share(Shape::CLogRepeat(term), |wg| {
+            wg.add_clogs(CLogs::repeat(term))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pi_pi_mul_has_one_pi() {
+        let mut b = Builder::create();
+        let (pi, also_pi) = (b.pi(), b.pi());
+        assert_eq!(pi, also_pi);
+        let square = b.arith(Arith::mul(), pi, also_pi);
+        let wg = b.try_into_workgroup().unwrap();
+        assert_eq!(wg.schedule().collect::<Vec<_>>(), vec![pi, square]);
+    }
+
+    #[test]
+    fn shares_up_to_scaling_and_symmetry() {
+        let mut b = Builder::create();
+        let (pi, e) = (b.pi(), b.e());
+        let sum = b.arith(Arith::add(), pi, e);
+        assert_eq!(b.arith(Arith::add(), e, pi), sum);
+        let doubled = Arith::create(Arith::add().mat.map(|c| c * 2));
+        assert_eq!(b.arith(doubled, pi, e), sum);
+        // (but x - y isn't y - x)
+        let diff = b.arith(Arith::sub(), pi, e);
+        assert_ne!(b.arith(Arith::sub(), e, pi), diff);
+        assert_eq!(b.workgroup().schedule().count(), 5);
+    }
+}
@@ -0,0 +1,106 @@
+use std::fmt::Write;
+
+use crate::{
+    unit::{Unit, UnitUnion},
+    workgroup::{Slot, Workgroup},
+};
+
+// graphviz output, for eyeballing what a workgroup actually looks like
+// (especially the ones that grow themselves, like log2's locks)
+// units that wrap workgroups get their insides drawn as a cluster
+// with dashed edges for the slots they feed by hand
+impl Workgroup {
+    pub fn to_dot(&self) -> String {
+        let mut out = String::from("digraph workgroup {\n    node [shape=box];\n");
+        self.write_dot(&mut out, "u", 1);
+        out.push_str("}\n");
+        out
+    }
+
+    // node names are prefixed with the path of units we're nested in
+    // so that ids from different workgroups don't collide
+    // (writing to a string can't fail)
+    fn write_dot(&self, out: &mut String, prefix: &str, depth: usize) {
+        let indent = "    ".repeat(depth);
+        for id in self.schedule() {
+            let unit = self.get_unit(id);
+            let name = format!("{prefix}{}", id.index);
+            let mut label = format!("#{} {}", id.index, unit.inner.kind());
+            if let UnitUnion::Lft(lft) = &unit.inner
+                && !lft.egest_enabled
+            {
+                label.push_str(" (locked)");
+            }
+            // (one line per matrix, since they can grow at very different rates)
+            let mut bits = String::new();
+            for (matrix, size) in unit.inner.matrix_bits() {
+                write!(bits, "{matrix}: {size} bits\\n").unwrap();
+            }
+            writeln!(
+                out,
+                "{indent}{name} [label=\"{label}\\n{bits}z = {:?}\"];",
+                unit.z
+            )
+            .unwrap();
+            for (slot, input) in [("x", unit.x), ("y", unit.y)] {
+                if let Some(input) = input {
+                    writeln!(
+                        out,
+                        "{indent}{prefix}{} -> {name} [label=\"{slot}\"];",
+                        input.index
+                    )
+                    .unwrap();
+                }
+            }
+            if let Some(inner) = unit.inner.inner_workgroup() {
+                let inner_prefix = format!("{name}_");
+                writeln!(out, "{indent}subgraph cluster_{name} {{").unwrap();
+                writeln!(out, "{indent}    label=\"{label}\";").unwrap();
+                inner.write_dot(out, &inner_prefix, depth + 1);
+                writeln!(out, "{indent}}}").unwrap();
+                for (fed, slot) in inner.hand_fed.iter() {
+                    let slot = match slot {
+                        Slot::X => "x",
+                        Slot::Y => "y",
+                    };
+                    writeln!(
+                        out,
+                        "{indent}{name} -> {inner_prefix}{} [label=\"{slot}\", style=dashed];",
+                        fed.index
+                    )
+                    .unwrap();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        unit::cfrac::FromCFrac,
+        workgroup::{Workgroup, exp::ExpTaylor},
+    };
+
+    #[test]
+    fn draws_inner_workgroups_as_clusters() {
+        let mut wg = Workgroup::create();
+        let half = wg.add_from_cfrac(FromCFrac::simple(vec![0, 2]));
+        let exp = wg.add_exp_taylor(ExpTaylor::create(), half);
+        for _ in 0..30 {
+            wg.next_term(exp).unwrap();
+        }
+        let dot = wg.to_dot();
+        let (half, exp) = (half.index, exp.index);
+        assert!(dot.starts_with("digraph workgroup {"));
+        assert!(dot.contains(&format!("u{half} -> u{exp} [label=\"x\"];")));
+        assert!(dot.contains(&format!("subgraph cluster_u{exp} {{")));
+        // (exp feeds its first units by hand)
+        assert!(dot.contains(&format!("u{exp} -> u{exp}_0 [label=\"x\", style=dashed];")));
+        assert!(dot.contains(&format!("u{exp}_0 [label=\"#0 arith\\nmat: ")));
+        // exp's own matrices, each with its own size
+        for matrix in ["next_taylor", "next_out", "sum", "egested"] {
+            assert!(dot.contains(&format!("\\n{matrix}: ")), "{matrix}");
+        }
+    }
+}
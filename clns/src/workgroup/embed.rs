@@ -0,0 +1,272 @@
+use crate::{
+    Term,
+    reduce::ReduceStats,
+    unit::{Arity, Input, UndefinedReason, Unit},
+    workgroup::{Slot, UnitId, ValidationError, Workgroup, WorkgroupPhase},
+};
+
+// embeddable workgroups are workgroups that mark which units are to be externally fed
+// and which unit can be treated as output
+// so that they can coherently implement Unit
+// and be embedded within another workgroup
+// (i.e. user-defined composite functions, packaged up as a reusable unit)
+//
+// the inner workgroup runs in lockstep with the outer one:
+// each outer phase runs exactly the same inner phase
+// so an embedded workgroup behaves exactly like its units would if they were spliced into the outer graph
+//
+// port slots should be left unwired in the inner workgroup
+// (otherwise the unit ingests twice per cycle; validation catches this)
+#[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(remote = "Self")
+)]
+pub struct EmbeddableWorkgroup {
+    inner: Workgroup,
+    // inner slots that get fed whatever we ingest on x
+    x_ports: Vec<(UnitId, Slot)>,
+    // inner slots that get fed whatever we ingest on y
+    y_ports: Vec<(UnitId, Slot)>,
+    egester: UnitId,
+}
+
+impl EmbeddableWorkgroup {
+    pub fn create(inner: Workgroup, egester: UnitId) -> Self {
+        Self {
+            inner,
+            x_ports: vec![],
+            y_ports: vec![],
+            egester,
+        }
+    }
+
+    // our x gets fed into the given slot of the given inner unit
+    pub fn port_x(mut self, id: UnitId, slot: Slot) -> Self {
+        self.inner.hand_feed(id, slot);
+        self.x_ports.push((id, slot));
+        self
+    }
+
+    // our y gets fed into the given slot of the given inner unit
+    pub fn port_y(mut self, id: UnitId, slot: Slot) -> Self {
+        self.inner.hand_feed(id, slot);
+        self.y_ports.push((id, slot));
+        self
+    }
+
+    pub fn inner(&self) -> &Workgroup {
+        &self.inner
+    }
+
+    pub fn egester(&self) -> UnitId {
+        self.egester
+    }
+
+    // we only care about x/y if there's some port for them to go to
+    pub fn arity(&self) -> Arity {
+        let input = |ports: &Vec<(UnitId, Slot)>| {
+            if ports.is_empty() {
+                Input::Unused
+            } else {
+                Input::Required
+            }
+        };
+        Arity {
+            x: input(&self.x_ports),
+            y: input(&self.y_ports),
+        }
+    }
+
+    // the inner graph's own problems, plus an egester it doesn't know about
+    // (ports are hand-fed slots of the inner workgroup, so that covers them too)
+    pub fn validate(&self) -> Result<(), Vec<ValidationError>> {
+        let mut errors = self.inner.validate().err().unwrap_or_default();
+        if self.inner.try_get_unit(self.egester).is_none() {
+            errors.push(ValidationError::UnknownUnit { unit: self.egester });
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    // the outer workgroup only calls ingest_x/ingest_y if we're wired to an x/y
+    // so we might have to run the inner phases we missed ourselves
+    fn advance_to(&mut self, phase: WorkgroupPhase) {
+        while self.inner.current_phase != phase {
+            self.inner.cycle_inner();
+        }
+    }
+
+    fn feed(&mut self, slot_of_ports: Slot, term: Term) {
+        let ports = match slot_of_ports {
+            Slot::X => &self.x_ports,
+            Slot::Y => &self.y_ports,
+        };
+        // (the same way a wired input gets ingested, so metrics and provenance see it too)
+        let track = self.inner.track_metrics;
+        for (id, slot) in ports.iter() {
+            self.inner.get_unit_mut(*id).ingest(*slot, term, track);
+        }
+    }
+
+    // (only for the outer workgroup, see UnitConcrete::inner_workgroup_mut)
+    pub(super) fn workgroup_mut(&mut self) -> &mut Workgroup {
+        &mut self.inner
+    }
+
+    // our inner workgroup got a fresh tag when it was deserialized
+    #[cfg(feature = "serde")]
+    fn restored(&mut self) {
+        let adopt = |id: UnitId| self.inner.adopt(id).unwrap_or(id);
+        for (id, _) in self.x_ports.iter_mut().chain(self.y_ports.iter_mut()) {
+            *id = adopt(*id);
+        }
+        self.egester = adopt(self.egester);
+    }
+}
+
+#[cfg(feature = "serde")]
+serde_restored!(EmbeddableWorkgroup);
+
+impl Unit for EmbeddableWorkgroup {
+    fn ingest_x(&mut self, x: Term) {
+        self.advance_to(WorkgroupPhase::IngestX);
+        self.feed(Slot::X, x);
+        self.inner.cycle_inner();
+    }
+
+    fn ingest_y(&mut self, y: Term) {
+        self.advance_to(WorkgroupPhase::IngestY);
+        self.feed(Slot::Y, y);
+        self.inner.cycle_inner();
+    }
+
+    fn egest_z(&mut self) -> Term {
+        self.advance_to(WorkgroupPhase::EgestZ);
+        self.inner.cycle_inner();
+        self.inner.get_unit(self.egester).z
+    }
+
+    fn max_bits(&self) -> u64 {
+        self.inner.max_bits()
+    }
+
+    fn reduce_stats(&self) -> ReduceStats {
+        self.inner.reduce_stats()
+    }
+
+    // whatever went wrong inside
+    // (undefined coming in through a port shows up as an undefined input on our side anyway)
+    fn undefined_reason(&self) -> UndefinedReason {
+        self.inner
+            .undefined_origin(self.egester)
+            .ok()
+            .flatten()
+            .map(|origin| origin.reason)
+            .unwrap_or(UndefinedReason::Unknown)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        Error,
+        unit::{UnitUnion, arith::Arith, cfrac::consts, lft::Lft},
+        workgroup::{EvalMode, UnitConcrete},
+    };
+
+    #[test]
+    fn ports_count_towards_metrics() {
+        let mut inner = Workgroup::create();
+        let double = inner.add_lft(Lft::affine(2, 0), None);
+        inner.set_track_metrics(true);
+        let embedded = EmbeddableWorkgroup::create(inner, double).port_x(double, Slot::X);
+        let mut wg = Workgroup::create();
+        let pi = wg.add_from_cfrac(consts::pi());
+        let id = wg.add_embedded(embedded, Some(pi), None);
+        for _ in 0..60 {
+            wg.cycle().unwrap();
+        }
+        let inner = wg.get_unit(id).inner.inner_workgroup().unwrap();
+        let metrics = inner.get_unit(double).metrics;
+        assert!(metrics.ingested_x > 0);
+        assert!(metrics.egested > 0);
+    }
+
+    // x*y + x, embedded and spliced in, fed pi and e
+    fn embedded_and_spliced(mode: EvalMode) -> (Workgroup, UnitId, UnitId) {
+        let mut inner = Workgroup::create();
+        // (the ports' slots stay unwired)
+        let mut arith = |arith: Arith, x: Option<UnitId>| {
+            inner.add_unit(UnitConcrete {
+                inner: UnitUnion::Arith(arith),
+                x,
+                y: None,
+                z: Default::default(),
+                metrics: Default::default(),
+                provenance: Default::default(),
+            })
+        };
+        let prod = arith(Arith::mul(), None);
+        let sum = arith(Arith::add(), Some(prod));
+        let embedded = EmbeddableWorkgroup::create(inner, sum)
+            .port_x(prod, Slot::X)
+            .port_y(prod, Slot::Y)
+            .port_x(sum, Slot::Y);
+        let mut wg = Workgroup::create();
+        wg.set_mode(mode);
+        let pi = wg.add_from_cfrac(consts::pi());
+        let e = wg.add_from_cfrac(consts::e());
+        let embedded = wg.add_embedded(embedded, Some(pi), Some(e));
+        let prod = wg.add_arith(Arith::mul(), pi, e);
+        let spliced = wg.add_arith(Arith::add(), prod, pi);
+        (wg, embedded, spliced)
+    }
+
+    #[test]
+    fn same_as_splicing_in() {
+        // (in push mode the terms come out in the very same phases)
+        let (mut wg, embedded, spliced) = embedded_and_spliced(EvalMode::Push);
+        let mut real = 0;
+        for _ in 0..300 {
+            wg.cycle().unwrap();
+            let z = wg.get_unit(embedded).z;
+            assert_eq!(z, wg.get_unit(spliced).z);
+            real += (z != Term::Empty) as usize;
+        }
+        assert!(real > 0);
+        let (mut wg, embedded, spliced) = embedded_and_spliced(EvalMode::PULL);
+        let terms = (0..100)
+            .map(|_| wg.next_term(embedded).unwrap())
+            .collect::<Vec<_>>();
+        let spliced = (0..100).map(|_| wg.next_term(spliced).unwrap());
+        assert!(terms.iter().any(|term| *term != Term::Empty));
+        assert!(terms.into_iter().eq(spliced));
+    }
+
+    #[test]
+    fn inner_failures_come_out_of_cycle() {
+        let (mut wg, embedded, _) = embedded_and_spliced(EvalMode::Push);
+        wg.cycle().unwrap();
+        // (nothing outside this module can get at the inner workgroup, so this can't really happen)
+        let UnitUnion::EmbeddableWorkgroup(unit) =
+            &mut wg.units.get_mut(&embedded.index).unwrap().inner
+        else {
+            unreachable!()
+        };
+        let egester = unit.egester;
+        unit.inner.set_inputs(egester, Some(egester), None);
+        let Err(Error::InvalidGraph(errors)) = wg.cycle() else {
+            panic!("a broken inner workgroup should fail the cycle");
+        };
+        assert!(matches!(
+            errors.as_slice(),
+            [ValidationError::Embedded { unit, .. }] if *unit == embedded
+        ));
+    }
+}
@@ -0,0 +1,337 @@
+use std::time::{Duration, Instant};
+
+use num_rational::BigRational;
+
+use crate::{
+    Error, Term,
+    interval::Interval,
+    reduce::ReduceStats,
+    unit::{Unit, lft::Lft},
+    workgroup::{UnitId, Workgroup},
+};
+
+// some expressions never settle (cmp of two equal irrationals, sqrt(2)^2 - 2)
+// and some settle, but only by way of enormous coefficients
+// so rather than asking for n terms and hoping, you can say how much you're willing to spend
+
+// None means no limit
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Budget {
+    // one cycle here is one term out of the output (i.e. one next_term)
+    pub cycles: Option<u64>,
+    pub time: Option<Duration>,
+    // summed over every unit's biggest coefficient (see Workgroup::total_bits)
+    pub bits: Option<u64>,
+    // how many terms (ø included) the value can spend closing in on the same rational
+    // before we call it a stall (see Stall)
+    // (keep it well above a few dozen: an irrational can sit near one of its convergents for a while)
+    pub stall: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Limit {
+    Cycles,
+    Time,
+    Bits,
+}
+
+// the value has spent a while closing in on a single rational
+// (the output either egesting ø, or terms that only ever narrow things down around it)
+// which usually means it's exactly that rational and we're stuck deciding which side of it we're on
+// (e.g. sqrt(2) * sqrt(2) sitting right on 2, or cmp of two equal irrationals sitting on 0)
+// we can't tell that apart from being really close, so this just says how close
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Stall {
+    // the value is within `within` of `near`
+    pub near: BigRational,
+    pub within: BigRational,
+    // how many terms it's been closing in for
+    pub cycles: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Approximation {
+    // everything the output egested, ø included
+    pub terms: Vec<Term>,
+    // where the terms so far put the value (None if they say it's undefined or exactly oo)
+    // (everything, if they're all ø)
+    pub interval: Option<Interval>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Evaluation {
+    // got all the terms asked for, or the value was pinned down before that
+    Complete(Approximation),
+    // ran out of budget first
+    Partial(Approximation, Limit),
+    // gave up on a stalled output (the interval includes what the output unit knows but hasn't said)
+    Stalled(Approximation, Stall),
+}
+
+impl Evaluation {
+    pub fn approximation(&self) -> &Approximation {
+        match self {
+            Self::Complete(approx) | Self::Partial(approx, _) | Self::Stalled(approx, _) => approx,
+        }
+    }
+
+    pub fn into_approximation(self) -> Approximation {
+        match self {
+            Self::Complete(approx) | Self::Partial(approx, _) | Self::Stalled(approx, _) => approx,
+        }
+    }
+
+    pub fn stall(&self) -> Option<&Stall> {
+        match self {
+            Self::Stalled(_, stall) => Some(stall),
+            Self::Complete(_) | Self::Partial(..) => None,
+        }
+    }
+
+    pub fn limit(&self) -> Option<Limit> {
+        match self {
+            Self::Partial(_, limit) => Some(*limit),
+            Self::Complete(_) | Self::Stalled(..) => None,
+        }
+    }
+}
+
+impl Workgroup {
+    // every unit's biggest coefficient, added up
+    // (a rough measure of how much memory the coefficients are taking)
+    pub fn total_bits(&self) -> u64 {
+        self.units.values().map(|unit| unit.inner.max_bits()).sum()
+    }
+
+    // every unit's reducer stats, added up (see reduce.rs)
+    pub fn reduce_stats(&self) -> ReduceStats {
+        let mut stats = ReduceStats::default();
+        for unit in self.units.values() {
+            stats.merge(&unit.inner.reduce_stats());
+        }
+        stats
+    }
+
+    // takes terms from out until `terms` real (non-ø) ones have come out
+    // or until it egests oo or ! (after which there's nothing left to learn)
+    // limits are checked between terms
+    // (and the time limit while a term's being worked out too, see next_term_before)
+    pub fn evaluate(
+        &mut self,
+        out: UnitId,
+        terms: usize,
+        budget: &Budget,
+    ) -> Result<Evaluation, Error> {
+        let start = Instant::now();
+        let deadline = budget.time.map(|max| start + max);
+        // (locked, it only tracks where the terms put us)
+        let mut to_rat = Lft {
+            egest_enabled: false,
+            ..Lft::identity()
+        };
+        let mut egested = vec![];
+        let mut real = 0;
+        let mut cycles = 0;
+        // (where the value was when it started closing in on what, and how many terms ago)
+        let mut stall_start: Option<(Interval, BigRational)> = None;
+        let mut stalled = 0;
+        let end = loop {
+            if real >= terms {
+                break None;
+            }
+            if budget.cycles.is_some_and(|max| cycles >= max) {
+                break Some(Err(Limit::Cycles));
+            }
+            if budget.time.is_some_and(|max| start.elapsed() >= max) {
+                break Some(Err(Limit::Time));
+            }
+            if budget.bits.is_some_and(|max| self.total_bits() > max) {
+                break Some(Err(Limit::Bits));
+            }
+            let Some(term) = self.next_term_before(out, deadline)? else {
+                break Some(Err(Limit::Time));
+            };
+            cycles += 1;
+            egested.push(term);
+            to_rat.ingest_x(term);
+            if term != Term::Empty {
+                real += 1;
+            }
+            if let Some(patience) = budget.stall {
+                let now = self.value_interval(out, &to_rat, real);
+                // (the simplest rational in an interval is still the simplest in any
+                // narrower one that contains it, so there's no need to work it out again)
+                if let (Some((_, near)), Some(now)) = (&stall_start, &now)
+                    && now.contains(near)
+                {
+                    stalled += 1;
+                } else {
+                    stall_start = now.clone().and_then(|now| {
+                        let near = now.simplest()?;
+                        Some((now, near))
+                    });
+                    stalled = 0;
+                }
+                if stalled >= patience
+                    && let (Some((then, near)), Some(now)) = (&stall_start, &now)
+                    && let Some(stall) = closing_in(then, now, near, stalled)
+                {
+                    break Some(Ok(stall));
+                }
+            }
+            if matches!(term, Term::Inf | Term::Undefined) {
+                break None;
+            }
+        };
+        let interval = decided(&to_rat, real);
+        Ok(match end {
+            None => Evaluation::Complete(Approximation {
+                terms: egested,
+                interval,
+            }),
+            Some(Err(limit)) => Evaluation::Partial(
+                Approximation {
+                    terms: egested,
+                    interval,
+                },
+                limit,
+            ),
+            Some(Ok(stall)) => Evaluation::Stalled(
+                Approximation {
+                    terms: egested,
+                    interval: self.value_interval(out, &to_rat, real),
+                },
+                stall,
+            ),
+        })
+    }
+
+    // where the terms so far plus whatever the output unit is sitting on put the value
+    // (just the terms so far if the unit can't say what it's sitting on)
+    fn value_interval(&self, out: UnitId, to_rat: &Lft, real: usize) -> Option<Interval> {
+        match self.try_get_unit(out)?.inner.pending() {
+            Some(pending) => to_rat.image(&pending),
+            None => decided(to_rat, real),
+        }
+    }
+}
+
+// where the terms so far put the value
+// (an lft that hasn't ingested anything still assumes its input's in [1, oo], so no real terms is everything)
+fn decided(to_rat: &Lft, real: usize) -> Option<Interval> {
+    if real == 0 {
+        Some(Interval::everything())
+    } else {
+        to_rat.interval()
+    }
+}
+
+// a stall, if the interval got narrower but kept near (then's simplest rational) in it
+// (an interval closing in on an irrational keeps turning up new, more complicated rationals)
+fn closing_in(then: &Interval, now: &Interval, near: &BigRational, cycles: u64) -> Option<Stall> {
+    if now.width()? >= then.width()? || !now.contains(near) {
+        return None;
+    }
+    let lo = now.lo.rational()?;
+    let hi = now.hi.rational()?;
+    let within = (near - lo).max(hi - near);
+    Some(Stall {
+        near: near.clone(),
+        within,
+        cycles,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        unit::{
+            arith::Arith,
+            cfrac::{FromCFrac, consts},
+            cmp::Compare,
+            sqrt::Sqrt,
+        },
+        workgroup::WorkgroupPhase,
+    };
+
+    // sqrt(2) and sqrt(2), which nothing can ever tell apart
+    fn sqrt_2s() -> (Workgroup, UnitId, UnitId) {
+        let mut wg = Workgroup::create();
+        let two = wg.add_from_cfrac(FromCFrac::simple(vec![2]));
+        let a = wg.add_sqrt(Sqrt::create(), two);
+        let b = wg.add_sqrt(Sqrt::create(), two);
+        (wg, a, b)
+    }
+
+    #[test]
+    fn nothing_decided_is_everything() {
+        let (mut wg, a, b) = sqrt_2s();
+        let cmp = wg.add_compare(Compare::create(), a, b);
+        let budget = Budget {
+            cycles: Some(20),
+            ..Budget::default()
+        };
+        let evaluation = wg.evaluate(cmp, 1, &budget).unwrap();
+        assert_eq!(evaluation.limit(), Some(Limit::Cycles));
+        let approx = evaluation.approximation();
+        assert!(approx.terms.iter().all(|term| *term == Term::Empty));
+        assert_eq!(approx.interval, Some(Interval::everything()));
+    }
+
+    #[test]
+    fn deadline_is_checked_between_phases() {
+        let (mut wg, a, _) = sqrt_2s();
+        let past = Instant::now();
+        assert_eq!(wg.next_term_before(a, Some(past)), Ok(None));
+        assert_eq!(wg.current_phase, WorkgroupPhase::EgestZ);
+        let budget = Budget {
+            time: Some(Duration::ZERO),
+            ..Budget::default()
+        };
+        let evaluation = wg.evaluate(a, 10, &budget).unwrap();
+        assert_eq!(evaluation.limit(), Some(Limit::Time));
+        assert_eq!(
+            evaluation.approximation().interval,
+            Some(Interval::everything())
+        );
+    }
+
+    #[test]
+    fn stalls_closing_in_on_zero() {
+        // (the output keeps egesting real terms here, just ones that never get past 0)
+        let (mut wg, a, b) = sqrt_2s();
+        let square = wg.add_arith(Arith::mul(), a, b);
+        let two = wg.add_from_cfrac(FromCFrac::simple(vec![2]));
+        let out = wg.add_arith(Arith::sub(), square, two);
+        let budget = Budget {
+            cycles: Some(500),
+            stall: Some(30),
+            ..Budget::default()
+        };
+        let evaluation = wg.evaluate(out, usize::MAX, &budget).unwrap();
+        let stall = evaluation.stall().expect("should stall");
+        assert_eq!(stall.near, BigRational::from_integer(0.into()));
+        assert!(stall.within < BigRational::new(1.into(), 1024.into()));
+        let interval = evaluation.approximation().interval.as_ref().unwrap();
+        assert!(interval.contains(&stall.near));
+    }
+
+    #[test]
+    fn irrationals_dont_stall() {
+        let mut wg = Workgroup::create();
+        let pi = wg.add_from_cfrac(consts::pi());
+        let budget = Budget {
+            cycles: Some(500),
+            stall: Some(64),
+            ..Budget::default()
+        };
+        let evaluation = wg.evaluate(pi, usize::MAX, &budget).unwrap();
+        assert_eq!(evaluation.limit(), Some(Limit::Cycles));
+    }
+}
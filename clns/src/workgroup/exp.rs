@@ -1,13 +1,24 @@
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use num_traits::Signed;
+
 use crate::{
     Term,
-    unit::{Unit, UnitUnion, arith::Arith},
-    workgroup::{UnitConcrete, UnitId, Workgroup},
+    interval::Interval,
+    reduce::{self, ReduceStats},
+    unit::{Unit, UnitUnion, arith::Arith, lft::Lft},
+    workgroup::{Slot, UnitConcrete, UnitId, Workgroup},
 };
 
 // e^x = 1 + x + x^2/2! + x^3/3! + ...
 // for the best results,
 // input values 0 < x < 1
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(remote = "Self")
+)]
 pub struct ExpTaylor {
     inner: Workgroup,
     // we manually ingest x inputs into these
@@ -24,26 +35,41 @@ pub struct ExpTaylor {
     // we feedback on next out with the last out term
     // that way they can seamlessly take over upon addition of another layer
     next_out: Arith,
-    // for real xs, allows us to force add another layer
-    real_egests_since_last_layer: u32,
+    // where x is, going by what we've ingested (locked)
+    // (these three are boxed to keep UnitUnion from ballooning)
+    x: Box<Lft>,
+    // where the partial sum is, going by what the last out has egested (locked)
+    // (out speculates and retracts like any arith, so its terms stay in here)
+    sum: Box<Lft>,
+    // where we've said the output is, going by what we've egested (locked)
+    // (we only say what the partial sum and its tail bound agree on, so we never retract)
+    // (that's assuming x is where its terms so far put it, which we have no way to check:
+    // an input that speculates, like an arith or a sqrt, can still retract and take us with it)
+    egested: Box<Lft>,
 }
 
 impl Unit for ExpTaylor {
     fn ingest_x(&mut self, x: Term) {
-        self.inner.get_unit_mut(self.init_term).inner.ingest_x(x);
-        self.inner.get_unit_mut(self.init_term).inner.ingest_y(x);
-        self.inner.get_unit_mut(self.init_out).inner.ingest_x(x);
-        for id in self.taylor_terms.iter() {
-            self.inner.get_unit_mut(*id).inner.ingest_x(x);
+        // (the same way a wired input gets ingested, so metrics and provenance see it too)
+        let track = self.inner.track_metrics;
+        let fed = [
+            (self.init_term, Slot::X),
+            (self.init_term, Slot::Y),
+            (self.init_out, Slot::X),
+        ];
+        let taylor_terms = self.taylor_terms.iter().map(|id| (*id, Slot::X));
+        for (id, slot) in fed.into_iter().chain(taylor_terms) {
+            self.inner.get_unit_mut(id).ingest(slot, x, track);
         }
         // cycle twice to both flush x and y through the taylor terms/outs
-        self.inner.cycle();
-        self.inner.cycle();
+        self.inner.cycle_inner();
+        self.inner.cycle_inner();
         // println!("ok so {:?}", self.inner.get_unit(*self.taylor_terms.last().unwrap()));
 
         // gotta keep the next taylor term apprised of developments in x
         self.next_taylor.ingest_x(x);
         self.next_next_taylor.ingest_x(x);
+        self.x.ingest_x(x);
         // println!("next taylor term {:?}", self.next_taylor.mat);
     }
 
@@ -54,84 +80,208 @@ impl Unit for ExpTaylor {
     }
 
     fn egest_z(&mut self) -> Term {
-        self.inner.cycle();
+        // (once we've said oo or !, there's nothing left to say)
+        let Some(said) = self.egested.inverse() else {
+            return if self.egested.is_undefined() {
+                Term::Undefined
+            } else {
+                Term::Inf
+            };
+        };
+
+        self.inner.cycle_inner();
 
         let last_taylor = self
             .inner
             .get_unit_mut(*self.taylor_terms.last().unwrap())
             .z;
-        self.next_taylor.ingest_y(last_taylor);
 
-        let out = self.inner.get_unit_mut(*self.outs.last().unwrap());
+        let out = self.inner.get_unit(*self.outs.last().unwrap());
         let out_term = out.z;
-
-        if out_term != Term::Empty {
-            self.real_egests_since_last_layer += 1;
+        if out_term == Term::Undefined {
+            // (only an undefined x gets us here)
+            self.egested.ingest_x(Term::Undefined);
+            return Term::Undefined;
         }
-        if out_term == Term::Inf
-            // this is a made-up heuristic
-            // this constant seems impossible to get right though
-            // since the balance between previous taylor terms and new ones is hard to estimate
-            || self.real_egests_since_last_layer >= 16 * self.taylor_terms.len() as u32
-        {
-            println!(
-                "{} | {}",
-                self.real_egests_since_last_layer,
-                self.taylor_terms.len()
+        let mut sum = self.sum.clone();
+        sum.ingest_x(out_term);
+        // (out's state is already past out_term)
+        // (no falling back on sum's own interval: a speculating out can be anywhere, [1, oo] or not)
+        let partial = out.inner.pending().and_then(|pending| sum.image(&pending));
+
+        let around = partial.and_then(|partial| self.around(&partial));
+        let term = around
+            .as_ref()
+            .and_then(|(exp, _)| decide(&said, &self.egested, exp))
+            .unwrap_or(Term::Empty);
+        // (e^x is never oo for an x we can bound, so an oo out of the sum means it's not caught up)
+        let too_rough = out_term == Term::Inf
+            || term == Term::Empty && around.is_some_and(|(_, tail_heavy)| tail_heavy);
+        if too_rough {
+            debug!(
+                "exp: adding taylor layer {} at output interval {:?}",
+                self.taylor_terms.len() + 1,
+                self.egested.interval(),
             );
+            // the new layer picks up both the last taylor term's z and the held back out term
+            // through its y inputs next cycle, so neither gets fed to next_taylor/next_out/sum here
+            // (otherwise they'd get ingested twice)
             self.add_layer();
-            self.real_egests_since_last_layer = 0;
             return Term::Empty;
         }
 
+        self.next_taylor.ingest_y(last_taylor);
+
         // we are preemptively feedbacking our out term
         // into and from what will be the next out
         // so that it can seamlessly take over when we add another layer
         self.next_out.ingest_y(out_term);
         self.next_out.egest(out_term);
+        self.sum = sum;
+
+        self.egested.ingest_x(term);
+        term
+    }
 
-        out_term
+    fn max_bits(&self) -> u64 {
+        [&self.next_taylor, &self.next_next_taylor, &self.next_out]
+            .iter()
+            .map(|arith| reduce::max_bits(&arith.mat))
+            .chain([
+                self.x.max_bits(),
+                self.sum.max_bits(),
+                self.egested.max_bits(),
+            ])
+            .fold(self.inner.max_bits(), u64::max)
+    }
+
+    fn matrix_bits(&self) -> Vec<(&'static str, u64)> {
+        vec![
+            ("next_taylor", reduce::max_bits(&self.next_taylor.mat)),
+            (
+                "next_next_taylor",
+                reduce::max_bits(&self.next_next_taylor.mat),
+            ),
+            ("next_out", reduce::max_bits(&self.next_out.mat)),
+            ("x", self.x.max_bits()),
+            ("sum", self.sum.max_bits()),
+            ("egested", self.egested.max_bits()),
+        ]
+    }
+
+    fn reduce_stats(&self) -> ReduceStats {
+        let mut stats = self.inner.reduce_stats();
+        for arith in [&self.next_taylor, &self.next_next_taylor, &self.next_out] {
+            stats.merge(&arith.reduce_stats());
+        }
+        for lft in [&self.x, &self.sum, &self.egested] {
+            stats.merge(&lft.reduce_stats());
+        }
+        stats
     }
 }
 
 impl ExpTaylor {
+    pub fn inner(&self) -> &Workgroup {
+        &self.inner
+    }
+
     pub fn create() -> Self {
         let mut wg = Workgroup::create();
         let init_term = wg.add_unit(UnitConcrete {
-            inner: UnitUnion::Arith(Arith::create([1, 0, 0, 0, 0, 0, 0, 2].map(|i| i.into()))),
+            inner: UnitUnion::Arith(Arith::bilinear(1, 0, 0, 0, 0, 0, 0, 2)),
             x: None,
             y: None,
             z: Default::default(),
+            metrics: Default::default(),
+            provenance: Default::default(),
         });
         let init_out = wg.add_unit(UnitConcrete {
-            inner: UnitUnion::Arith(Arith::create([0, 1, 1, 1, 0, 0, 0, 1].map(|i| i.into()))),
+            inner: UnitUnion::Arith(Arith::bilinear(0, 1, 1, 1, 0, 0, 0, 1)),
             x: None,
             y: Some(init_term),
             z: Default::default(),
+            metrics: Default::default(),
+            provenance: Default::default(),
         });
         let first_term = wg.add_unit(UnitConcrete {
-            inner: UnitUnion::Arith(Arith::create([1, 0, 0, 0, 0, 0, 0, 3].map(|i| i.into()))),
+            inner: UnitUnion::Arith(Arith::bilinear(1, 0, 0, 0, 0, 0, 0, 3)),
             x: None,
             y: Some(init_term),
             z: Default::default(),
+            metrics: Default::default(),
+            provenance: Default::default(),
         });
         let first_out = wg.add_unit(UnitConcrete {
-            inner: UnitUnion::Arith(Arith::create([0, 1, 1, 0, 0, 0, 0, 1].map(|i| i.into()))),
+            inner: UnitUnion::Arith(Arith::add()),
             x: Some(first_term),
             y: Some(init_out),
             z: Default::default(),
+            metrics: Default::default(),
+            provenance: Default::default(),
         });
+        // (see ingest_x)
+        wg.hand_feed(init_term, Slot::X);
+        wg.hand_feed(init_term, Slot::Y);
+        wg.hand_feed(init_out, Slot::X);
+        wg.hand_feed(first_term, Slot::X);
         Self {
             inner: wg,
             init_term,
             init_out,
             taylor_terms: vec![first_term],
             outs: vec![first_out],
-            next_taylor: Arith::create([1, 0, 0, 0, 0, 0, 0, 4].map(|i| i.into())),
-            next_next_taylor: Arith::create([1, 0, 0, 0, 0, 0, 0, 5].map(|i| i.into())),
-            next_out: Arith::create([0, 1, 1, 0, 0, 0, 0, 1].map(|i| i.into())),
-            real_egests_since_last_layer: 0,
+            next_taylor: Arith::bilinear(1, 0, 0, 0, 0, 0, 0, 4),
+            next_next_taylor: Arith::bilinear(1, 0, 0, 0, 0, 0, 0, 5),
+            next_out: Arith::add(),
+            x: Box::new(Lft {
+                egest_enabled: false,
+                ..Lft::identity()
+            }),
+            sum: Box::new(Lft {
+                egest_enabled: false,
+                ..Lft::identity()
+            }),
+            egested: Box::new(Lft {
+                egest_enabled: false,
+                ..Lft::identity()
+            }),
+        }
+    }
+
+    // the partial sum we're egesting from goes up to x^n/n!
+    fn degree(&self) -> usize {
+        self.taylor_terms.len() + 2
+    }
+
+    // how far e^x can be from the partial sum, going by lagrange's remainder:
+    // |e^x - sum| <= e^max(x, 0) |x|^(n+1) / (n+1)!
+    // (with 3^ceil(x) standing in for e^x)
+    // None while x isn't bounded yet
+    fn tail_bound(&self) -> Option<BigRational> {
+        let x = self.x.interval()?;
+        let (lo, hi) = (x.lo.rational()?, x.hi.rational()?);
+        // (rounded up to a 64th: x can run to thousands of bits, and this gets raised to the nth power)
+        let grid = BigRational::from_integer(64.into());
+        let abs = (lo.abs().max(hi.abs()) * &grid).ceil() / grid;
+        let n = self.degree() as i32 + 1;
+        let factorial = (2..=n).fold(BigInt::from(1), |acc, k| acc * k);
+        let mut bound = abs.pow(n) / factorial;
+        if hi.is_positive() {
+            let e_bound = i32::try_from(hi.ceil().to_integer()).ok()?;
+            bound *= BigRational::from_integer(3.into()).pow(e_bound);
         }
+        Some(bound)
+    }
+
+    // where e^x is, going by where the partial sum is and how far e^x can be from it
+    // and whether that's mostly down to the tail (i.e. whether another layer would help)
+    // None while either's unbounded
+    fn around(&self, partial: &Interval) -> Option<(Interval, bool)> {
+        let tail = self.tail_bound()?;
+        let (lo, hi) = (partial.lo.rational()?, partial.hi.rational()?);
+        let tail_heavy = tail > hi - lo;
+        Some((Interval::closed(lo - &tail, hi + &tail), tail_heavy))
     }
 
     pub fn add_layer(&mut self) {
@@ -143,16 +293,19 @@ impl ExpTaylor {
             x: None,
             y: Some(*self.taylor_terms.last().unwrap()), // (there will always be a last)
             z: Default::default(),
+            metrics: Default::default(),
+            provenance: Default::default(),
         });
+        self.inner.hand_feed(new_last_term, Slot::X);
         self.next_taylor.mat = self.next_next_taylor.mat.clone();
-        self.next_next_taylor.mat[0] *= self.taylor_terms.len() + 4;
-        self.next_next_taylor.mat[1] *= self.taylor_terms.len() + 4;
-        self.next_next_taylor.mat[2] *= self.taylor_terms.len() + 4;
-        self.next_next_taylor.mat[3] *= self.taylor_terms.len() + 4;
-        self.next_next_taylor.mat[4] *= self.taylor_terms.len() + 5;
-        self.next_next_taylor.mat[5] *= self.taylor_terms.len() + 5;
-        self.next_next_taylor.mat[6] *= self.taylor_terms.len() + 5;
-        self.next_next_taylor.mat[7] *= self.taylor_terms.len() + 5;
+        self.next_next_taylor.mat[0] *= self.taylor_terms.len() as i64 + 4;
+        self.next_next_taylor.mat[1] *= self.taylor_terms.len() as i64 + 4;
+        self.next_next_taylor.mat[2] *= self.taylor_terms.len() as i64 + 4;
+        self.next_next_taylor.mat[3] *= self.taylor_terms.len() as i64 + 4;
+        self.next_next_taylor.mat[4] *= self.taylor_terms.len() as i64 + 5;
+        self.next_next_taylor.mat[5] *= self.taylor_terms.len() as i64 + 5;
+        self.next_next_taylor.mat[6] *= self.taylor_terms.len() as i64 + 5;
+        self.next_next_taylor.mat[7] *= self.taylor_terms.len() as i64 + 5;
         self.taylor_terms.push(new_last_term);
         let out = self.inner.add_unit(UnitConcrete {
             inner: UnitUnion::Arith(Arith::create(self.next_out.mat.clone())),
@@ -160,7 +313,110 @@ impl ExpTaylor {
             x: Some(*self.taylor_terms.last().unwrap()),
             y: Some(*self.outs.last().unwrap()),
             z: Default::default(),
+            metrics: Default::default(),
+            provenance: Default::default(),
         });
         self.outs.push(out);
     }
+
+    // (only for the outer workgroup, see UnitConcrete::inner_workgroup_mut)
+    pub(super) fn workgroup_mut(&mut self) -> &mut Workgroup {
+        &mut self.inner
+    }
+
+    // our inner workgroup got a fresh tag when it was deserialized
+    #[cfg(feature = "serde")]
+    fn restored(&mut self) {
+        let adopt = |id: &mut UnitId| *id = self.inner.adopt(*id).unwrap_or(*id);
+        adopt(&mut self.init_term);
+        adopt(&mut self.init_out);
+        self.taylor_terms.iter_mut().for_each(adopt);
+        self.outs.iter_mut().for_each(adopt);
+    }
+}
+
+#[cfg(feature = "serde")]
+serde_restored!(ExpTaylor);
+
+// the next term we can say, given e^x is somewhere in exp
+// (said takes the output back to where our next term starts, see ExpTaylor::egested)
+fn decide(said: &Lft, egested: &Lft, exp: &Interval) -> Option<Term> {
+    // only the part of exp we haven't already ruled out counts
+    // (past the ends of what we've said, said runs off through oo, and egest_z can't tell)
+    let exp = if egested.mat == Lft::identity().mat {
+        // (nothing said yet, and the identity's [1, oo] isn't a claim, see decided in eval.rs)
+        exp.clone()
+    } else {
+        exp.intersection(&egested.interval()?)?
+    };
+    let mut rest = said * &Lft::from_interval(&exp)?;
+    rest.egest_enabled = true;
+    Some(rest.egest_z())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        unit::{
+            cfrac::{FromCFrac, consts},
+            sqrt::Sqrt,
+        },
+        workgroup::{EvalMode, eval::Budget},
+    };
+
+    // (adds x to the workgroup)
+    type Input = fn(&mut Workgroup) -> UnitId;
+
+    fn simple(wg: &mut Workgroup, x: i64) -> UnitId {
+        wg.add_from_cfrac(FromCFrac::simple(vec![x]))
+    }
+
+    #[test]
+    fn never_retracts() {
+        // e^x is between these for each x
+        // (sqrt(pi - e) speculates and retracts on the way in)
+        let inputs: [(Input, _); 4] = [
+            (|wg| simple(wg, 1), (271828182, 271828183)),
+            (|wg| simple(wg, 2), (738905609, 738905610)),
+            (
+                |wg| {
+                    let two = simple(wg, 2);
+                    wg.add_sqrt(Sqrt::create(), two)
+                },
+                (411325037, 411325038),
+            ),
+            (
+                |wg| {
+                    let pi = wg.add_from_cfrac(consts::pi());
+                    let e = wg.add_from_cfrac(consts::e());
+                    let diff = wg.add_arith(Arith::sub(), pi, e);
+                    wg.add_sqrt(Sqrt::create(), diff)
+                },
+                (191673537, 191673538),
+            ),
+        ];
+        // (pull feeds exp until it has something to say, so it gets a lot further per term)
+        for (mode, cycles) in [(EvalMode::Push, 500), (EvalMode::PULL, 60)] {
+            for (input, (lo, hi)) in inputs {
+                let mut wg = Workgroup::create();
+                wg.set_mode(mode);
+                let x = input(&mut wg);
+                let exp = wg.add_exp_taylor(ExpTaylor::create(), x);
+                let budget = Budget {
+                    cycles: Some(cycles),
+                    ..Budget::default()
+                };
+                let evaluation = wg.evaluate(exp, usize::MAX, &budget).unwrap();
+                let approx = evaluation.approximation();
+                assert!(!approx.terms.contains(&Term::Neg), "{:?}", mode);
+                let interval = approx.interval.as_ref().unwrap();
+                let scale = BigInt::from(100000000);
+                let lo = BigRational::new(lo.into(), scale.clone());
+                let hi = BigRational::new(hi.into(), scale);
+                assert!(interval.lo.rational().is_some_and(|got| *got >= lo));
+                assert!(interval.hi.rational().is_some_and(|got| *got <= hi));
+            }
+        }
+    }
 }
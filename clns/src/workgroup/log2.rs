@@ -1,7 +1,12 @@
+use std::cmp::Ordering;
+
+use num_rational::BigRational;
+
 use crate::{
-    Term,
-    unit::{Unit, UnitUnion, arith::Arith, lft::Lft},
-    workgroup::{UnitConcrete, UnitId, Workgroup},
+    Term, coeff,
+    reduce::{self, ReduceStats},
+    unit::{UndefinedReason, Unit, UnitUnion, arith::Arith, lft::Lft},
+    workgroup::{Slot, UnitConcrete, UnitId, Workgroup},
 };
 
 // this is the algorithm from https://mathr.co.uk/web/continued-logarithm.html#Logarithm
@@ -12,7 +17,12 @@ use crate::{
 // and a little silly on account of me not designing this library super well
 // this is definitely research quality code lmao
 // so far this only works on input > 1 but it does work
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(remote = "Self")
+)]
 pub struct Log2 {
     wg: Workgroup,
     // hand-fed
@@ -35,6 +45,10 @@ impl Log2 {
     // create new locked copies of them
     // with the mediant using the new mediant and one of the sides the old mediant
     fn add_layer(&mut self, left: bool) {
+        debug!(
+            "log2: adding a layer, mediant becomes the {} bound",
+            if left { "left" } else { "right" }
+        );
         // unlock everything
         let unit = self.wg.get_unit_mut(self.mediant_lock);
         if let UnitUnion::Lft(lft) = &mut unit.inner {
@@ -52,28 +66,26 @@ impl Log2 {
             // set the left to the old mediant
             self.left_lock = self.wg.add_lft(
                 Lft {
-                    mat: [1.into(), 0.into(), 0.into(), 1.into()],
                     egest_enabled: false,
+                    ..Lft::identity()
                 },
                 Some(self.mediant_lock),
             );
             // create new mediant and right lock
-            let mediant = self.wg.add_arith(
-                Arith::create([1, 0, 0, 0, 0, 0, 0, 1].map(|i| i.into())),
-                self.mediant_lock,
-                self.right_lock,
-            );
+            let mediant = self
+                .wg
+                .add_arith(Arith::mul(), self.mediant_lock, self.right_lock);
             self.mediant_lock = self.wg.add_lft(
                 Lft {
-                    mat: [1.into(), 0.into(), 0.into(), 1.into()],
                     egest_enabled: false,
+                    ..Lft::identity()
                 },
                 Some(mediant),
             );
             self.right_lock = self.wg.add_lft(
                 Lft {
-                    mat: [1.into(), 0.into(), 0.into(), 1.into()],
                     egest_enabled: false,
+                    ..Lft::identity()
                 },
                 Some(self.right_lock),
             );
@@ -86,41 +98,45 @@ impl Log2 {
             // set the right to the old mediant
             self.right_lock = self.wg.add_lft(
                 Lft {
-                    mat: [1.into(), 0.into(), 0.into(), 1.into()],
                     egest_enabled: false,
+                    ..Lft::identity()
                 },
                 Some(self.mediant_lock),
             );
             // create new mediant and left lock
-            let mediant = self.wg.add_arith(
-                Arith::create([1, 0, 0, 0, 0, 0, 0, 1].map(|i| i.into())),
-                self.mediant_lock,
-                self.left_lock,
-            );
+            let mediant = self
+                .wg
+                .add_arith(Arith::mul(), self.mediant_lock, self.left_lock);
             self.mediant_lock = self.wg.add_lft(
                 Lft {
-                    mat: [1.into(), 0.into(), 0.into(), 1.into()],
                     egest_enabled: false,
+                    ..Lft::identity()
                 },
                 Some(mediant),
             );
             self.left_lock = self.wg.add_lft(
                 Lft {
-                    mat: [1.into(), 0.into(), 0.into(), 1.into()],
                     egest_enabled: false,
+                    ..Lft::identity()
                 },
                 Some(self.left_lock),
             );
         }
+        // the lock we just replaced on the side we moved is dead weight
+        // whenever it was itself an old mediant (nothing else reads it)
+        self.wg
+            .collect_garbage(&[self.x, self.left_lock, self.right_lock, self.mediant_lock]);
     }
 }
 
 impl Unit for Log2 {
     // (x^a)/(2^c) <= 1 <= (x^b)/(2^d)
     fn ingest_x(&mut self, x: Term) {
-        self.wg.get_unit_mut(self.x).inner.ingest_x(x);
-        self.wg.cycle();
-        self.wg.cycle();
+        // (the same way a wired input gets ingested, so metrics and provenance see it too)
+        let track = self.wg.track_metrics;
+        self.wg.get_unit_mut(self.x).ingest(Slot::X, x, track);
+        self.wg.cycle_inner();
+        self.wg.cycle_inner();
     }
 
     fn ingest_y(&mut self, _: Term) {
@@ -128,53 +144,61 @@ impl Unit for Log2 {
     }
 
     fn egest_z(&mut self) -> Term {
-        self.wg.cycle();
+        self.wg.cycle_inner();
 
         if self.lft.is_inf() { return self.lft.egest_z() }
 
         let med = self.wg.get_unit_mut(self.mediant_lock);
         if let UnitUnion::Lft(lft) = &med.inner {
             if lft.is_nonpositive() {
+                trace!("log2: input is nonpositive, so we're undefined");
                 return Term::Undefined;
             }
             // println!("{:?}", lft);
-            let ((n1, d1), (n2, d2)) = lft.intervals();
+            // (undefined or oo, we'll just let the lft say so)
+            let Some(interval) = lft.interval() else {
+                return self.lft.egest_z();
+            };
+            let one = BigRational::from_integer(1.into());
+            let half = BigRational::new(1.into(), 2.into());
             // x <= 1/2 TODO
-            if (n1.clone() << 1) <= d1 && (n2.clone() << 1) <= d2 {
+            if interval.hi.cmp_rational(&half) != Ordering::Greater {
                 // self.lft.mat[0] = 0.into();
                 // self.lft.mat[1] = 0.into();
                 // self.lft.mat[2] = 1.into();
                 // self.lft.mat[3] = 1.into();
                 // return self.lft.egest_z();
-                println!("less than 1/2");
+                debug!("log2: input is at most 1/2, which isn't supported yet");
                 return Term::Undefined;
             }
             // we're > 1
-            if n1 > d1 && n2 > d2 {
+            if interval.lo.cmp_rational(&one) == Ordering::Greater {
                 // we're > 1
                 // left becomes mediant
                 // println!("> 1");
                 self.add_layer(true);
-                self.lft.mat[0] += self.lft.mat[1].clone();
-                self.lft.mat[2] += self.lft.mat[3].clone();
+                coeff::add_idx(&mut self.lft.mat, 0, 1);
+                coeff::add_idx(&mut self.lft.mat, 2, 3);
             }
             // we're < 1
-            if n1 < d1 && n2 < d2 {
+            if interval.hi.cmp_rational(&one) == Ordering::Less {
                 // we're < 1
                 // right becomes mediant
                 // println!("< 1");
                 self.add_layer(false);
-                self.lft.mat[1] += self.lft.mat[0].clone();
-                self.lft.mat[3] += self.lft.mat[2].clone();
+                coeff::add_idx(&mut self.lft.mat, 1, 0);
+                coeff::add_idx(&mut self.lft.mat, 3, 2);
             }
             // we're = 1
-            if n1 == d1 && n2 == d2 {
+            if interval.lo.cmp_rational(&one) == Ordering::Equal
+                && interval.hi.cmp_rational(&one) == Ordering::Equal
+            {
                 // we're exactly 1
                 // doesn't matter which we choose cause we're on the way done
                 // println!("= 1");
                 self.add_layer(true);
-                self.lft.mat[0] += self.lft.mat[1].clone();
-                self.lft.mat[2] += self.lft.mat[3].clone();
+                coeff::add_idx(&mut self.lft.mat, 0, 1);
+                coeff::add_idx(&mut self.lft.mat, 2, 3);
                 self.lft.mat[1] = self.lft.mat[0].clone();
                 self.lft.mat[3] = self.lft.mat[2].clone();
             }
@@ -184,57 +208,67 @@ impl Unit for Log2 {
 
         self.lft.egest_z()
     }
+
+    fn max_bits(&self) -> u64 {
+        self.wg.max_bits().max(reduce::max_bits(&self.lft.mat))
+    }
+
+    fn matrix_bits(&self) -> Vec<(&'static str, u64)> {
+        vec![("lft", reduce::max_bits(&self.lft.mat))]
+    }
+
+    fn reduce_stats(&self) -> ReduceStats {
+        let mut stats = self.wg.reduce_stats();
+        stats.merge(&self.lft.reduce_stats());
+        stats
+    }
+
+    fn undefined_reason(&self) -> UndefinedReason {
+        match &self.wg.get_unit(self.mediant_lock).inner {
+            UnitUnion::Lft(lft) if lft.is_nonpositive() => UndefinedReason::LogOfNonpositive,
+            UnitUnion::Lft(_) => UndefinedReason::Unsupported,
+            _ => UndefinedReason::Unknown,
+        }
+    }
 }
 
 impl Log2 {
+    pub fn inner(&self) -> &Workgroup {
+        &self.wg
+    }
+
     pub fn create() -> Self {
         let mut wg = Workgroup::create();
         let x = wg.add_unit(UnitConcrete {
-            inner: UnitUnion::Lft(Lft {
-                mat: [1.into(), 0.into(), 0.into(), 1.into()],
-                egest_enabled: true,
-            }),
+            inner: UnitUnion::Lft(Lft::identity()),
             x: None,
             y: None,
             z: Default::default(),
+            metrics: Default::default(),
+            provenance: Default::default(),
         });
-        let left = wg.add_lft(
-            Lft {
-                mat: [1.into(), 0.into(), 0.into(), 1.into()],
-                egest_enabled: true,
-            },
-            Some(x),
-        );
+        wg.hand_feed(x, Slot::X);
+        let left = wg.add_lft(Lft::identity(), Some(x));
         let left_lock = wg.add_lft(
             Lft {
-                mat: [1.into(), 0.into(), 0.into(), 1.into()],
                 egest_enabled: false,
+                ..Lft::identity()
             },
             Some(left),
         );
-        let right = wg.add_lft(
-            Lft {
-                mat: [1.into(), 1.into(), 2.into(), 2.into()],
-                egest_enabled: true,
-            },
-            None,
-        );
+        let right = wg.add_lft(Lft::from_rational(1, 2), None);
         let right_lock = wg.add_lft(
             Lft {
-                mat: [1.into(), 0.into(), 0.into(), 1.into()],
                 egest_enabled: false,
+                ..Lft::identity()
             },
             Some(right),
         );
-        let mediant = wg.add_arith(
-            Arith::create([1, 0, 0, 0, 0, 0, 0, 1].map(|i| i.into())),
-            left,
-            right,
-        );
+        let mediant = wg.add_arith(Arith::mul(), left, right);
         let mediant_lock = wg.add_lft(
             Lft {
-                mat: [1.into(), 0.into(), 0.into(), 1.into()],
                 egest_enabled: false,
+                ..Lft::identity()
             },
             Some(mediant),
         );
@@ -244,10 +278,25 @@ impl Log2 {
             left_lock,
             right_lock,
             mediant_lock,
-            lft: Lft {
-                mat: [0.into(), 1.into(), 1.into(), 0.into()],
-                egest_enabled: true,
-            },
+            lft: Lft::reciprocal(),
         }
     }
+
+    // (only for the outer workgroup, see UnitConcrete::inner_workgroup_mut)
+    pub(super) fn workgroup_mut(&mut self) -> &mut Workgroup {
+        &mut self.wg
+    }
+
+    // our workgroup got a fresh tag when it was deserialized
+    #[cfg(feature = "serde")]
+    fn restored(&mut self) {
+        let adopt = |id: UnitId| self.wg.adopt(id).unwrap_or(id);
+        self.x = adopt(self.x);
+        self.left_lock = adopt(self.left_lock);
+        self.right_lock = adopt(self.right_lock);
+        self.mediant_lock = adopt(self.mediant_lock);
+    }
 }
+
+#[cfg(feature = "serde")]
+serde_restored!(Log2);
@@ -0,0 +1,187 @@
+use std::time::{Duration, Instant};
+
+use crate::{
+    Term,
+    reduce::ReduceStats,
+    unit::Unit,
+    workgroup::{Slot, UnitConcrete, UnitId, Workgroup},
+};
+
+// what a unit has been up to
+// (off by default, since timing every ingest/egest isn't free)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct UnitMetrics {
+    // real terms only, ø doesn't count
+    pub ingested_x: u64,
+    pub ingested_y: u64,
+    // egests that gave a real term
+    pub egested: u64,
+    // egests that gave ø
+    pub stalls: u64,
+    // biggest coefficient as of the last egest
+    pub max_bits: u64,
+    // time spent in ingest/egest
+    pub time: Duration,
+    // the unit's reducers as of the last egest (see reduce.rs)
+    pub reduce: ReduceStats,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct WorkgroupMetrics {
+    // in schedule order
+    pub units: Vec<(UnitId, UnitMetrics)>,
+    // everything summed up (max_bits and reduce.peak_bits are the max instead)
+    pub total: UnitMetrics,
+}
+
+impl UnitConcrete {
+    // the workgroup ingests/egests through these so that the metrics (and provenance) stay up to date
+    pub(super) fn ingest(&mut self, slot: Slot, term: Term, track: bool) {
+        let start = track.then(Instant::now);
+        match slot {
+            Slot::X => self.inner.ingest_x(term),
+            Slot::Y => self.inner.ingest_y(term),
+        }
+        self.record_ingest(slot, term);
+        if let Some(start) = start {
+            self.metrics.time += start.elapsed();
+            if term != Term::Empty {
+                match slot {
+                    Slot::X => self.metrics.ingested_x += 1,
+                    Slot::Y => self.metrics.ingested_y += 1,
+                }
+            }
+        }
+    }
+
+    pub(super) fn egest(&mut self, track: bool) -> Term {
+        let start = track.then(Instant::now);
+        self.z = self.inner.egest_z();
+        self.record_egest();
+        if let Some(start) = start {
+            self.metrics.time += start.elapsed();
+            match self.z {
+                Term::Empty => self.metrics.stalls += 1,
+                _ => self.metrics.egested += 1,
+            }
+            self.metrics.max_bits = self.inner.max_bits();
+            self.metrics.reduce = self.inner.reduce_stats();
+        }
+        self.z
+    }
+}
+
+impl Workgroup {
+    // (units' inner workgroups follow along, including ones added later,
+    // so their own units' metrics are there to look at too)
+    pub fn set_track_metrics(&mut self, track: bool) {
+        self.track_metrics = track;
+        for unit in self.units.values_mut() {
+            if let Some(inner) = unit.inner_workgroup_mut() {
+                inner.set_track_metrics(track);
+            }
+        }
+    }
+
+    pub fn reset_metrics(&mut self) {
+        for unit in self.units.values_mut() {
+            unit.metrics = UnitMetrics::default();
+            if let Some(inner) = unit.inner_workgroup_mut() {
+                inner.reset_metrics();
+            }
+        }
+    }
+
+    pub fn metrics(&self) -> WorkgroupMetrics {
+        let mut total = UnitMetrics::default();
+        let units = self
+            .schedule()
+            .map(|id| {
+                let metrics = self.get_unit(id).metrics;
+                total.ingested_x += metrics.ingested_x;
+                total.ingested_y += metrics.ingested_y;
+                total.egested += metrics.egested;
+                total.stalls += metrics.stalls;
+                total.max_bits = total.max_bits.max(metrics.max_bits);
+                total.time += metrics.time;
+                total.reduce.merge(&metrics.reduce);
+                (id, metrics)
+            })
+            .collect();
+        WorkgroupMetrics { units, total }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::unit::{
+        arith::Arith,
+        cfrac::{FromCFrac, consts},
+    };
+    use crate::workgroup::{exp::ExpTaylor, log2::Log2};
+
+    #[test]
+    fn counts_ingests_and_egests() {
+        let mut wg = Workgroup::create();
+        wg.set_track_metrics(true);
+        let pi = wg.add_from_cfrac(consts::pi());
+        let e = wg.add_from_cfrac(consts::e());
+        let sum = wg.add_arith(Arith::add(), pi, e);
+        let half = wg.add_from_cfrac(FromCFrac::simple(vec![0, 2]));
+        let exp = wg.add_exp_taylor(ExpTaylor::create(), half);
+        let log = wg.add_log2(Log2::create(), pi);
+        for _ in 0..50 {
+            wg.next_term(sum).unwrap();
+        }
+        let metrics = wg.metrics();
+        let of = |id| {
+            metrics
+                .units
+                .iter()
+                .find(|(unit, _)| *unit == id)
+                .unwrap()
+                .1
+        };
+        // (push mode, so everything egests once per term)
+        for (id, _) in metrics.units.iter() {
+            assert_eq!(of(*id).egested + of(*id).stalls, 50);
+        }
+        assert_eq!(of(pi).ingested_x, 0);
+        // (ø doesn't count, and the last phase's egest hasn't made it in yet)
+        let pi_terms = of(pi).egested;
+        assert!(of(sum).ingested_x > 0 && of(sum).ingested_x <= pi_terms);
+        assert!(of(sum).ingested_y > 0);
+        assert!(of(sum).max_bits > 0);
+        assert_eq!(
+            metrics.total.egested,
+            metrics.units.iter().map(|(_, m)| m.egested).sum::<u64>()
+        );
+        // exp and log2 feed their inner workgroups by hand, and that counts too
+        for id in [exp, log] {
+            let inner = wg.get_unit(id).inner.inner_workgroup().unwrap();
+            let (first, slot) = *inner.hand_fed.first().unwrap();
+            assert_eq!(slot, Slot::X);
+            assert!(inner.get_unit(first).metrics.ingested_x > 0, "{:?}", id);
+        }
+
+        wg.reset_metrics();
+        assert_eq!(
+            wg.metrics(),
+            WorkgroupMetrics {
+                units: metrics
+                    .units
+                    .iter()
+                    .map(|(id, _)| (*id, UnitMetrics::default()))
+                    .collect(),
+                total: UnitMetrics::default(),
+            }
+        );
+        for id in [exp, log] {
+            let inner = wg.get_unit(id).inner.inner_workgroup().unwrap().metrics();
+            assert_eq!(inner.total, UnitMetrics::default());
+        }
+    }
+}
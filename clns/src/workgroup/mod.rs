@@ -1,25 +1,63 @@
 use crate::{
-    Term,
+    Error, Term,
     unit::{
-        CLogs, Unit, UnitUnion, arith::Arith, cfrac::FromCFrac, cmp::Compare, int::Modulo,
+        CLogs, Input, Unit, UnitUnion, arith::Arith, cfrac::FromCFrac, cmp::Compare, int::Modulo,
         lft::Lft, sqrt::Sqrt,
     },
-    workgroup::{exp::ExpTaylor, log2::Log2},
+    workgroup::{
+        embed::EmbeddableWorkgroup, exp::ExpTaylor, log2::Log2, metrics::UnitMetrics,
+        provenance::Provenance,
+    },
+};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::atomic::{AtomicU32, Ordering},
+    time::Instant,
 };
-use std::collections::HashMap;
 
+// serde for the types that need fixing up once they've been deserialized
+// (they derive with serde(remote = "Self"), which this forwards to, then call restored)
+#[cfg(feature = "serde")]
+macro_rules! serde_restored {
+    ($type:ty) => {
+        impl serde::Serialize for $type {
+            fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                <$type>::serialize(self, serializer)
+            }
+        }
+
+        impl<'de> serde::Deserialize<'de> for $type {
+            fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                let mut value = <$type>::deserialize(deserializer)?;
+                value.restored();
+                Ok(value)
+            }
+        }
+    };
+}
+
+pub mod build;
+mod dot;
+pub mod embed;
+pub mod eval;
 pub mod exp;
 pub mod log2;
+pub mod metrics;
+#[cfg(feature = "parallel")]
+mod par;
 pub mod powu;
+pub mod provenance;
+mod pull;
+pub mod stream;
 
-// TODO: ADD A WORKGROUP WRAPPER THAT HAS A UNIT IMPL
-// wouldn't that be so fun??? it'd let us seamlessly integrate e.g. dynamically growable exp workgroups
+// (see embed.rs for wrapping a whole workgroup up as a unit)
 
 // rather than the pull model of composing continued logarithm units together
 // which is what i originally reached for
 // i'm trying a push model --- we have a directed graph and do ingestion/egestion phases as a group
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum WorkgroupPhase {
     IngestX,
     IngestY,
@@ -37,247 +75,906 @@ impl WorkgroupPhase {
     }
 }
 
-// each UnitId integer must be unique within a workgroup
-// but not necessarily across workgroups
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
-pub struct UnitId(u32);
+// how next_term gets its terms
+// (both give the same value, but not the same terms; see pull.rs for how they differ)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum EvalMode {
+    // every unit ingests and egests every phase (see cycle)
+    Push,
+    // only the units the output needs do any work (see pull.rs)
+    // fuel caps how many units get fed in a single next_term
+    // (keep it small: a feed can be as slow as a whole push cycle
+    // and next_term gives ø when it runs out anyway, so nothing's lost)
+    Pull { fuel: u32 },
+}
+
+impl EvalMode {
+    pub const PULL: Self = Self::Pull { fuel: 64 };
+}
+
+// each UnitId is tagged with the workgroup that issued it
+// so that using an id with the wrong workgroup is caught rather than silently reading some other unit
+// (forks/clones of a workgroup share its tag, so ids carry over to them;
+// a deserialized workgroup gets a fresh one, since the tag it was serialized with
+// could belong to some other workgroup by now, so ids stored alongside it need to go through adopt)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct UnitId {
+    workgroup: u32,
+    index: u32,
+}
+
+// one of a unit's two inputs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Slot {
+    X,
+    Y,
+}
+
+// everything validate can find wrong with a workgroup
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ValidationError {
+    // wired to a unit that doesn't exist (or that some other workgroup issued)
+    DanglingInput {
+        unit: UnitId,
+        slot: Slot,
+        input: UnitId,
+    },
+    // a hand-fed slot or an output refers to a unit that doesn't exist (or is foreign)
+    UnknownUnit {
+        unit: UnitId,
+    },
+    // the unit needs this slot but it's neither wired nor hand-fed
+    MissingInput {
+        unit: UnitId,
+        slot: Slot,
+    },
+    // the unit never looks at this slot but something feeds it anyway
+    UnusedInput {
+        unit: UnitId,
+        slot: Slot,
+    },
+    // wired up and hand-fed, so it'd ingest twice per cycle
+    DoublyFed {
+        unit: UnitId,
+        slot: Slot,
+    },
+    // units on a feedback loop (or downstream of one), in id order
+    FeedbackLoop {
+        units: Vec<UnitId>,
+    },
+    // a unit's inner workgroup's own problems (an embedded workgroup's, or exp's or log2's)
+    Embedded {
+        unit: UnitId,
+        errors: Vec<ValidationError>,
+    },
+}
+
+static NEXT_WORKGROUP_TAG: AtomicU32 = AtomicU32::new(0);
 
 // we're not encoding the specific structure of the unit type
 // (do you take x? do you take y? do you egest anything meaningful?)
 // into our enums (cause that gets way too verbose)
 // we expect anyone who accesses a unit from a unit id to understand
 // the structure of that union
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct UnitConcrete {
     pub inner: UnitUnion,
     pub x: Option<UnitId>,
     pub y: Option<UnitId>,
     pub z: Term,
+    // (only kept up to date while the workgroup is tracking metrics)
+    pub metrics: UnitMetrics,
+    // where the unit's first undefined came from (always kept up to date)
+    pub provenance: Provenance,
 }
 
-#[derive(Debug)]
+// workgroups can be forked (cloned) mid-run
+// e.g. to keep evaluating one copy while inspecting another
+// or to try out a speculative decision in one branch and throw it away if it was wrong
+#[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(remote = "Self")
+)]
 pub struct Workgroup {
+    tag: u32,
+    // the tag it was serialized with, if it was deserialized (see adopt)
+    #[cfg_attr(feature = "serde", serde(skip))]
+    restored_from: Option<u32>,
     max_id: u32,
-    units: HashMap<UnitId, UnitConcrete>,
+    // (keyed by UnitId::index; the tag is the same for all of them)
+    units: BTreeMap<u32, UnitConcrete>,
+    // the order units get updated in within a phase
+    // it doesn't change the results (each phase only reads the z values from before it)
+    // but it keeps traces reproducible from run to run
+    // inputs always come before the units they feed, ties broken by id
+    schedule: Vec<u32>,
+    // slots that the owner of the workgroup feeds by hand rather than wiring up
+    // (e.g. the ports of an embedded workgroup)
+    hand_fed: BTreeSet<(UnitId, Slot)>,
+    mode: EvalMode,
+    // what each unit has egested so far (pull mode only)
+    taps: BTreeMap<u32, pull::Tap>,
+    // whether units record metrics as they go (see metrics.rs)
+    track_metrics: bool,
+    // whether the graph has been validated since it last changed
+    #[cfg_attr(feature = "serde", serde(skip))]
+    validated: bool,
+    // why we last failed to cycle, if we're some unit's inner workgroup (see cycle_inner)
+    #[cfg_attr(feature = "serde", serde(skip))]
+    failed: Option<Error>,
     pub current_phase: WorkgroupPhase,
 }
 
+#[cfg(feature = "serde")]
+serde_restored!(Workgroup);
+
 impl Workgroup {
     pub fn create() -> Workgroup {
         Workgroup {
+            tag: NEXT_WORKGROUP_TAG.fetch_add(1, Ordering::Relaxed),
+            restored_from: None,
             max_id: 0,
-            units: HashMap::new(),
+            units: BTreeMap::new(),
+            schedule: vec![],
+            hand_fed: BTreeSet::new(),
+            track_metrics: false,
+            validated: false,
+            failed: None,
+            mode: EvalMode::Push,
+            taps: BTreeMap::new(),
             current_phase: WorkgroupPhase::EgestZ,
         }
     }
 
+    // pick one before running anything
+    // (the two models keep different state, so switching midway makes a mess)
+    pub fn set_mode(&mut self, mode: EvalMode) {
+        self.mode = mode;
+    }
+
+    pub fn mode(&self) -> EvalMode {
+        self.mode
+    }
+
+    pub fn owns(&self, id: UnitId) -> bool {
+        id.workgroup == self.tag
+    }
+
+    // carries an id over from before the workgroup was serialized
+    // (None if neither this workgroup nor the one it was restored from issued it)
+    pub fn adopt(&self, id: UnitId) -> Option<UnitId> {
+        if self.owns(id) {
+            Some(id)
+        } else if self.restored_from == Some(id.workgroup) {
+            Some(UnitId {
+                workgroup: self.tag,
+                index: id.index,
+            })
+        } else {
+            None
+        }
+    }
+
+    // a fresh tag, and every id we hold moved over to it
+    // (inputs some other workgroup issued stay as they were, and still fail validation)
+    #[cfg(feature = "serde")]
+    fn restored(&mut self) {
+        self.restored_from = Some(self.tag);
+        self.tag = NEXT_WORKGROUP_TAG.fetch_add(1, Ordering::Relaxed);
+        let adopt = |wg: &Self, id: UnitId| wg.adopt(id).unwrap_or(id);
+        let mut units = std::mem::take(&mut self.units);
+        for unit in units.values_mut() {
+            unit.x = unit.x.map(|id| adopt(self, id));
+            unit.y = unit.y.map(|id| adopt(self, id));
+        }
+        self.units = units;
+        self.hand_fed = std::mem::take(&mut self.hand_fed)
+            .into_iter()
+            .map(|(id, slot)| (adopt(self, id), slot))
+            .collect();
+    }
+
+    // None if the id came from a different workgroup
+    pub fn try_get_unit(&self, id: UnitId) -> Option<&UnitConcrete> {
+        if !self.owns(id) {
+            return None;
+        }
+        self.units.get(&id.index)
+    }
+
+    pub fn try_get_unit_mut(&mut self, id: UnitId) -> Option<&mut UnitConcrete> {
+        if !self.owns(id) {
+            return None;
+        }
+        self.units.get_mut(&id.index)
+    }
+
     pub fn get_unit(&self, id: UnitId) -> &UnitConcrete {
-        // TODO find a way to tether unitids to the workgroup which issues them
+        assert!(
+            self.owns(id),
+            "{:?} was issued by a different workgroup",
+            id
+        );
         self.units
-            .get(&id)
+            .get(&id.index)
             .expect("if we issued an id then the unit should exist :P")
     }
 
     pub fn get_unit_mut(&mut self, id: UnitId) -> &mut UnitConcrete {
+        assert!(
+            self.owns(id),
+            "{:?} was issued by a different workgroup",
+            id
+        );
         self.units
-            .get_mut(&id)
+            .get_mut(&id.index)
             .expect("if we issued an id then the unit should exist :P")
     }
 
     fn new_id(&mut self) -> UnitId {
-        let id = UnitId(self.max_id);
+        let id = UnitId {
+            workgroup: self.tag,
+            index: self.max_id,
+        };
         self.max_id += 1;
         id
     }
 
-    pub fn add_unit(&mut self, unit: UnitConcrete) -> UnitId {
+    pub fn add_unit(&mut self, mut unit: UnitConcrete) -> UnitId {
         let id = self.new_id();
-        self.units.insert(id, unit);
+        // (its inner workgroup follows ours, see set_track_metrics)
+        if self.track_metrics
+            && let Some(inner) = unit.inner_workgroup_mut()
+        {
+            inner.set_track_metrics(true);
+        }
+        // ids are only issued here, so a new unit's inputs are already scheduled
+        // (or are foreign/dangling, which cycle will complain about)
+        // meaning we can just tack it onto the end
+        self.units.insert(id.index, unit);
+        self.schedule.push(id.index);
+        self.validated = false;
         id
     }
 
+    // add_unit, but refuses units wired to inputs this workgroup didn't issue (or has since removed)
+    // (add_unit lets those through and leaves them for validation to catch)
+    pub fn try_add_unit(&mut self, unit: UnitConcrete) -> Result<UnitId, Error> {
+        for input in [unit.x, unit.y].into_iter().flatten() {
+            if self.try_get_unit(input).is_none() {
+                return Err(Error::UnknownUnit(input));
+            }
+        }
+        Ok(self.add_unit(unit))
+    }
+
+    // marks a slot as fed by hand (through get_unit_mut) rather than wired to another unit
+    // so that validation doesn't complain about a required slot being unwired
+    pub fn hand_feed(&mut self, id: UnitId, slot: Slot) {
+        self.hand_fed.insert((id, slot));
+        self.validated = false;
+    }
+
+    // drops a unit, handing it back
+    // anything still wired to it will fail validation until it's rewired or dropped too
+    pub fn remove_unit(&mut self, id: UnitId) -> Option<UnitConcrete> {
+        if !self.owns(id) {
+            return None;
+        }
+        let unit = self.units.remove(&id.index)?;
+        self.schedule.retain(|&index| index != id.index);
+        self.hand_fed.retain(|(fed, _)| *fed != id);
+        self.taps.remove(&id.index);
+        self.forget_readers(|_, reader, _| reader == id.index);
+        self.validated = false;
+        Some(unit)
+    }
+
+    // drops every unit that the roots don't (transitively) depend on
+    // returns how many units got dropped
+    // (anything that's hand-fed but not upstream of a root has to be a root itself to survive)
+    pub fn collect_garbage(&mut self, roots: &[UnitId]) -> usize {
+        let mut live = BTreeSet::new();
+        let mut stack = roots
+            .iter()
+            .filter(|id| self.try_get_unit(**id).is_some())
+            .map(|id| id.index)
+            .collect::<Vec<_>>();
+        while let Some(index) = stack.pop() {
+            if !live.insert(index) {
+                continue;
+            }
+            let unit = &self.units[&index];
+            stack.extend(
+                [unit.x, unit.y]
+                    .into_iter()
+                    .flatten()
+                    .filter(|input| self.try_get_unit(*input).is_some())
+                    .map(|input| input.index),
+            );
+        }
+        let before = self.units.len();
+        self.units.retain(|index, _| live.contains(index));
+        self.schedule.retain(|index| live.contains(index));
+        self.hand_fed.retain(|(id, _)| live.contains(&id.index));
+        self.taps.retain(|index, _| live.contains(index));
+        self.forget_readers(|_, reader, _| !live.contains(&reader));
+        // nothing live was wired to what we dropped
+        // so the schedule is still in order and validity hasn't changed
+        before - self.units.len()
+    }
+
+    // rewires a unit's inputs and fixes up the schedule
+    // (poking at x/y through get_unit_mut works too, but leaves the schedule stale until the next reschedule)
+    pub fn set_inputs(&mut self, id: UnitId, x: Option<UnitId>, y: Option<UnitId>) {
+        let unit = self.get_unit_mut(id);
+        unit.x = x;
+        unit.y = y;
+        // (a slot keeps its place in a tap only if it still reads from the same producer)
+        let [x, y] = [x, y].map(|input| {
+            input
+                .filter(|input| self.owns(*input))
+                .map(|input| input.index)
+        });
+        self.forget_readers(|producer, reader, slot| {
+            let now = match slot {
+                Slot::X => x,
+                Slot::Y => y,
+            };
+            reader == id.index && now != Some(producer)
+        });
+        self.reschedule();
+    }
+
+    pub fn schedule(&self) -> impl Iterator<Item = UnitId> + '_ {
+        self.schedule.iter().map(|&index| UnitId {
+            workgroup: self.tag,
+            index,
+        })
+    }
+
+    // recomputes the schedule from scratch
+    // (units caught in a feedback loop have no valid topological order
+    // so they go at the end in id order; validation rejects them anyway)
+    pub fn reschedule(&mut self) {
+        let (mut schedule, stuck) = self.topological_order();
+        schedule.extend(stuck);
+        self.schedule = schedule;
+        self.validated = false;
+    }
+
+    // kahn's algorithm, lowest id first
+    // returns the ordered units and whichever ones couldn't be ordered
+    fn topological_order(&self) -> (Vec<u32>, Vec<u32>) {
+        let inputs = |unit: &UnitConcrete| {
+            [unit.x, unit.y]
+                .into_iter()
+                .flatten()
+                .filter(|i| self.try_get_unit(*i).is_some())
+                .map(|i| i.index)
+        };
+        let mut pending = BTreeMap::<u32, usize>::new();
+        let mut feeds = BTreeMap::<u32, Vec<u32>>::new();
+        for (&index, unit) in self.units.iter() {
+            let mut count = 0;
+            for input in inputs(unit) {
+                feeds.entry(input).or_default().push(index);
+                count += 1;
+            }
+            pending.insert(index, count);
+        }
+        let mut ready = pending
+            .iter()
+            .filter(|(_, count)| **count == 0)
+            .map(|(index, _)| *index)
+            .collect::<BTreeSet<_>>();
+        let mut order = Vec::with_capacity(self.units.len());
+        while let Some(index) = ready.pop_first() {
+            order.push(index);
+            pending.remove(&index);
+            for fed in feeds.get(&index).into_iter().flatten() {
+                let count = pending.get_mut(fed).unwrap();
+                *count -= 1;
+                if *count == 0 {
+                    ready.insert(*fed);
+                }
+            }
+        }
+        (order, pending.into_keys().collect())
+    }
+
+    // checks that every wire goes somewhere, that every unit is fed exactly what it consumes,
+    // and that there are no feedback loops
+    // (cycle does this for you whenever the graph has changed)
+    pub fn validate(&self) -> Result<(), Vec<ValidationError>> {
+        let mut errors = vec![];
+        for (&index, unit) in self.units.iter() {
+            let id = UnitId {
+                workgroup: self.tag,
+                index,
+            };
+            let arity = unit.inner.arity();
+            for (slot, wire, input) in [(Slot::X, unit.x, arity.x), (Slot::Y, unit.y, arity.y)] {
+                let hand_fed = self.hand_fed.contains(&(id, slot));
+                if let Some(wire) = wire {
+                    if self.try_get_unit(wire).is_none() {
+                        errors.push(ValidationError::DanglingInput {
+                            unit: id,
+                            slot,
+                            input: wire,
+                        });
+                    }
+                    if hand_fed {
+                        errors.push(ValidationError::DoublyFed { unit: id, slot });
+                    }
+                }
+                let fed = wire.is_some() || hand_fed;
+                match input {
+                    Input::Required if !fed => {
+                        errors.push(ValidationError::MissingInput { unit: id, slot })
+                    }
+                    Input::Unused if fed => {
+                        errors.push(ValidationError::UnusedInput { unit: id, slot })
+                    }
+                    _ => {}
+                }
+            }
+            let inner = match &unit.inner {
+                UnitUnion::EmbeddableWorkgroup(embedded) => embedded.validate(),
+                other => other.inner_workgroup().map_or(Ok(()), Workgroup::validate),
+            };
+            if let Err(inner) = inner {
+                errors.push(ValidationError::Embedded {
+                    unit: id,
+                    errors: inner,
+                });
+            }
+        }
+        for (id, _) in self.hand_fed.iter() {
+            if self.try_get_unit(*id).is_none() {
+                errors.push(ValidationError::UnknownUnit { unit: *id });
+            }
+        }
+        let (_, stuck) = self.topological_order();
+        if !stuck.is_empty() {
+            errors.push(ValidationError::FeedbackLoop {
+                units: stuck
+                    .into_iter()
+                    .map(|index| UnitId {
+                        workgroup: self.tag,
+                        index,
+                    })
+                    .collect(),
+            });
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
     pub fn add_arith(&mut self, unit: Arith, x: UnitId, y: UnitId) -> UnitId {
-        let id = self.new_id();
-        self.units.insert(
-            id,
-            UnitConcrete {
-                inner: UnitUnion::Arith(unit),
-                x: Some(x),
-                y: Some(y),
-                z: Default::default(),
-            },
-        );
-        id
+        self.add_unit(UnitConcrete {
+            inner: UnitUnion::Arith(unit),
+            x: Some(x),
+            y: Some(y),
+            z: Default::default(),
+            metrics: Default::default(),
+            provenance: Default::default(),
+        })
     }
 
     pub fn add_sqrt(&mut self, unit: Sqrt, x: UnitId) -> UnitId {
-        let id = self.new_id();
-        self.units.insert(
-            id,
-            UnitConcrete {
-                inner: UnitUnion::Sqrt(unit),
-                x: Some(x),
-                y: None,
-                z: Default::default(),
-            },
-        );
-        id
+        self.add_unit(UnitConcrete {
+            inner: UnitUnion::Sqrt(unit),
+            x: Some(x),
+            y: None,
+            z: Default::default(),
+            metrics: Default::default(),
+            provenance: Default::default(),
+        })
     }
 
     pub fn add_from_cfrac(&mut self, unit: FromCFrac) -> UnitId {
-        let id = self.new_id();
-        self.units.insert(
-            id,
-            UnitConcrete {
-                inner: UnitUnion::FromCFrac(unit),
-                x: None,
-                y: None,
-                z: Default::default(),
-            },
-        );
-        id
+        self.add_unit(UnitConcrete {
+            inner: UnitUnion::FromCFrac(unit),
+            x: None,
+            y: None,
+            z: Default::default(),
+            metrics: Default::default(),
+            provenance: Default::default(),
+        })
     }
 
     pub fn add_clogs(&mut self, unit: CLogs) -> UnitId {
-        let id = self.new_id();
-        self.units.insert(
-            id,
-            UnitConcrete {
-                inner: UnitUnion::CLogs(unit),
-                x: None,
-                y: None,
-                z: Default::default(),
-            },
-        );
-        id
+        self.add_unit(UnitConcrete {
+            inner: UnitUnion::CLogs(unit),
+            x: None,
+            y: None,
+            z: Default::default(),
+            metrics: Default::default(),
+            provenance: Default::default(),
+        })
     }
 
     pub fn add_lft(&mut self, unit: Lft, x: Option<UnitId>) -> UnitId {
-        let id = self.new_id();
-        self.units.insert(
-            id,
-            UnitConcrete {
-                inner: UnitUnion::Lft(unit),
-                x,
-                y: None,
-                z: Default::default(),
-            },
-        );
-        id
+        self.add_unit(UnitConcrete {
+            inner: UnitUnion::Lft(unit),
+            x,
+            y: None,
+            z: Default::default(),
+            metrics: Default::default(),
+            provenance: Default::default(),
+        })
     }
 
     pub fn add_modulo(&mut self, unit: Modulo, x: UnitId, y: UnitId) -> UnitId {
-        let id = self.new_id();
-        self.units.insert(
-            id,
-            UnitConcrete {
-                inner: UnitUnion::Modulo(unit),
-                x: Some(x),
-                y: Some(y),
-                z: Default::default(),
-            },
-        );
-        id
+        self.add_unit(UnitConcrete {
+            inner: UnitUnion::Modulo(unit),
+            x: Some(x),
+            y: Some(y),
+            z: Default::default(),
+            metrics: Default::default(),
+            provenance: Default::default(),
+        })
     }
 
     pub fn add_compare(&mut self, unit: Compare, x: UnitId, y: UnitId) -> UnitId {
-        let id = self.new_id();
-        self.units.insert(
-            id,
-            UnitConcrete {
-                inner: UnitUnion::Compare(unit),
-                x: Some(x),
-                y: Some(y),
-                z: Default::default(),
-            },
-        );
-        id
+        self.add_unit(UnitConcrete {
+            inner: UnitUnion::Compare(unit),
+            x: Some(x),
+            y: Some(y),
+            z: Default::default(),
+            metrics: Default::default(),
+            provenance: Default::default(),
+        })
     }
 
     // units that themselves wrap workgroups
 
     pub fn add_exp_taylor(&mut self, unit: ExpTaylor, x: UnitId) -> UnitId {
-        let id = self.new_id();
-        self.units.insert(
-            id,
-            UnitConcrete {
-                inner: UnitUnion::ExpTaylor(unit),
-                x: Some(x),
-                y: None,
-                z: Default::default(),
-            },
-        );
-        id
+        self.add_unit(UnitConcrete {
+            inner: UnitUnion::ExpTaylor(unit),
+            x: Some(x),
+            y: None,
+            z: Default::default(),
+            metrics: Default::default(),
+            provenance: Default::default(),
+        })
     }
 
     pub fn add_log2(&mut self, unit: Log2, x: UnitId) -> UnitId {
-        let id = self.new_id();
-        self.units.insert(
-            id,
-            UnitConcrete {
-                inner: UnitUnion::Log2(unit),
-                x: Some(x),
-                y: None,
-                z: Default::default(),
-            },
-        );
-        id
+        self.add_unit(UnitConcrete {
+            inner: UnitUnion::Log2(unit),
+            x: Some(x),
+            y: None,
+            z: Default::default(),
+            metrics: Default::default(),
+            provenance: Default::default(),
+        })
+    }
+
+    pub fn add_embedded(
+        &mut self,
+        unit: EmbeddableWorkgroup,
+        x: Option<UnitId>,
+        y: Option<UnitId>,
+    ) -> UnitId {
+        self.add_unit(UnitConcrete {
+            inner: UnitUnion::EmbeddableWorkgroup(unit),
+            x,
+            y,
+            z: Default::default(),
+            metrics: Default::default(),
+            provenance: Default::default(),
+        })
+    }
+
+    // the biggest coefficient in any unit, in bits
+    pub fn max_bits(&self) -> u64 {
+        self.units
+            .values()
+            .map(|unit| unit.inner.max_bits())
+            .max()
+            .unwrap_or(0)
+    }
+
+    // how the two inputs of a compare unit compare so far
+    pub fn comparison(&self, id: UnitId) -> Result<std::cmp::Ordering, Error> {
+        let unit = self.try_get_unit(id).ok_or(Error::UnknownUnit(id))?;
+        match &unit.inner {
+            UnitUnion::Compare(cmp) => cmp.cmp(),
+            other => Err(Error::WrongKind {
+                unit: id,
+                expected: "compare",
+                found: other.kind(),
+            }),
+        }
     }
 
-    pub fn cycle(&mut self) {
-        let keys = self.units.keys().cloned().collect::<Vec<_>>();
-        for id in keys {
-            match self.current_phase {
-                WorkgroupPhase::IngestX => {
-                    if let Some(x) = self.units[&id].x {
-                        let term = self.units.get(&x).unwrap().z;
-                        self.units.get_mut(&id).unwrap().inner.ingest_x(term);
+    fn ensure_valid(&mut self) -> Result<(), Error> {
+        if !self.validated {
+            self.validate()?;
+            self.validated = true;
+        }
+        Ok(())
+    }
+
+    // the next term out of the given unit, however the mode says to get it
+    // push: runs phases up to and including the next EgestZ and reads its z (which might be ø)
+    // pull: pulls just enough through the graph for one term (ø only if the fuel ran out)
+    pub fn next_term(&mut self, out: UnitId) -> Result<Term, Error> {
+        // (no deadline, so there's always a term)
+        Ok(self.next_term_before(out, None)?.unwrap_or(Term::Empty))
+    }
+
+    // next_term, but None if the deadline passes before the term's ready
+    // (push checks between phases, pull every time a unit gets fed,
+    // so a single slow egest can still overshoot it)
+    pub(super) fn next_term_before(
+        &mut self,
+        out: UnitId,
+        deadline: Option<Instant>,
+    ) -> Result<Option<Term>, Error> {
+        if self.try_get_unit(out).is_none() {
+            return Err(Error::UnknownUnit(out));
+        }
+        match self.mode {
+            EvalMode::Push => {
+                loop {
+                    if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                        return Ok(None);
                     }
-                }
-                WorkgroupPhase::IngestY => {
-                    if let Some(y) = self.units[&id].y {
-                        let term = self.units.get(&y).unwrap().z;
-                        self.units.get_mut(&id).unwrap().inner.ingest_y(term);
+                    let phase = self.current_phase;
+                    self.cycle()?;
+                    if phase == WorkgroupPhase::EgestZ {
+                        break;
                     }
                 }
-                WorkgroupPhase::EgestZ => {
-                    let unit = self.units.get_mut(&id).unwrap();
-                    unit.z = unit.inner.egest_z();
-                }
+                // (cycle can't have removed it)
+                Ok(Some(self.get_unit(out).z))
+            }
+            EvalMode::Pull { fuel } => {
+                self.ensure_valid()?;
+                let term = self.pull(out, fuel, deadline);
+                self.inner_failures()?;
+                Ok(term)
+            }
+        }
+    }
+
+    // runs one phase
+    // refuses to run (and changes nothing) if the graph doesn't validate
+    // or if a unit's inputs were rewired by hand to something that isn't here
+    pub fn cycle(&mut self) -> Result<(), Error> {
+        self.ensure_valid()?;
+        let (phase, track) = (self.current_phase, self.track_metrics);
+        for (index, input) in self.phase_inputs()? {
+            if let Some(unit) = self.units.get_mut(&index) {
+                unit.step(phase, input, track);
             }
         }
         self.current_phase = self.current_phase.next();
+        self.inner_failures()
+    }
+
+    // cycle, for workgroups that live inside a unit (exp, log2, embedded ones)
+    // those are either built by the unit itself or validated along with the outer workgroup
+    // so this shouldn't fail, but if it does the unit has nowhere to report it
+    // so it's kept for the outer workgroup to hand back from its cycle (see inner_failures)
+    // and we don't run anything until then
+    pub(super) fn cycle_inner(&mut self) {
+        if self.failed.is_none()
+            && let Err(err) = self.cycle()
+        {
+            self.failed = Some(err);
+        }
+    }
+
+    // the first failure to cycle any unit's inner workgroup had since we last checked
+    pub(super) fn inner_failures(&mut self) -> Result<(), Error> {
+        for (&index, unit) in self.units.iter_mut() {
+            let failed = unit
+                .inner_workgroup_mut()
+                .and_then(|inner| inner.failed.take());
+            let unit = UnitId {
+                workgroup: self.tag,
+                index,
+            };
+            match failed {
+                Some(Error::InvalidGraph(errors)) => {
+                    return Err(Error::InvalidGraph(vec![ValidationError::Embedded {
+                        unit,
+                        errors,
+                    }]));
+                }
+                Some(err) => return Err(err),
+                None => {}
+            }
+        }
+        Ok(())
+    }
+
+    // every unit in schedule order, with the z it'll ingest this phase (None if it won't)
+    // zs don't change during ingestion, so we can look every input up front
+    // (and a bad input fails here, before any unit has been touched)
+    pub(super) fn phase_inputs(&self) -> Result<Vec<(u32, Option<Term>)>, Error> {
+        self.schedule
+            .iter()
+            .map(|index| {
+                let unit = &self.units[index];
+                let input = match self.current_phase {
+                    WorkgroupPhase::IngestX => unit.x,
+                    WorkgroupPhase::IngestY => unit.y,
+                    WorkgroupPhase::EgestZ => None,
+                };
+                let term = match input {
+                    Some(input) => {
+                        Some(self.try_get_unit(input).ok_or(Error::UnknownUnit(input))?.z)
+                    }
+                    None => None,
+                };
+                Ok((*index, term))
+            })
+            .collect()
     }
 }
 
-// // TODO is this a good abstraction to unify underneath?
-// // embeddable workgroups are workgroups that mark which units are to be externally fed
-// // and which unit can be treated as output
-// // so that they can coherently implement Unit
-// // and be embedded within another workgroup
-// pub struct EmbeddableWorkgroup {
-//     inner: Workgroup,
-//     ingesters: Vec<UnitId>,
-//     egester: UnitId,
-// }
-
-// impl Unit for EmbeddableWorkgroup {
-//     fn ingest_x(&mut self, x: Term) {
-//         for id in self.ingesters.iter() {
-//             self.inner.get_unit_mut(*id).inner.ingest_x(x);
-//         }
-//         self.inner().cycle();
-//     }
-
-//     fn ingest_y(&mut self, y: Term) {
-//         for id in self.ingesters.iter() {
-//             self.inner.get_unit_mut(*id).inner.ingest_y(y);
-//         }
-//         self.inner().cycle();
-//     }
-
-//     fn egest_z(&mut self) -> Term {
-//         self.inner().cycle();
-//         self.inner.get_unit_mut(self.egester).inner.egest_z()
-//     }
-// }
+impl UnitConcrete {
+    // the workgroup the unit is built out of, if it is
+    // (only for us: changing an inner graph behind the outer workgroup's back would skip validation)
+    pub(super) fn inner_workgroup_mut(&mut self) -> Option<&mut Workgroup> {
+        match &mut self.inner {
+            UnitUnion::ExpTaylor(exp) => Some(exp.workgroup_mut()),
+            UnitUnion::Log2(log2) => Some(log2.workgroup_mut()),
+            UnitUnion::EmbeddableWorkgroup(embedded) => Some(embedded.workgroup_mut()),
+            _ => None,
+        }
+    }
+
+    // one unit's part of a phase (input comes from Workgroup::phase_inputs)
+    pub(super) fn step(&mut self, phase: WorkgroupPhase, input: Option<Term>, track: bool) {
+        match (phase, input) {
+            (WorkgroupPhase::IngestX, Some(term)) => self.ingest(Slot::X, term, track),
+            (WorkgroupPhase::IngestY, Some(term)) => self.ingest(Slot::Y, term, track),
+            (WorkgroupPhase::EgestZ, _) => {
+                self.egest(track);
+            }
+            (WorkgroupPhase::IngestX | WorkgroupPhase::IngestY, None) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn identity(wg: &mut Workgroup, x: Option<UnitId>) -> UnitId {
+        wg.add_lft(Lft::identity(), x)
+    }
+
+    #[test]
+    fn validate_rejects_feedback_loops() {
+        let mut wg = Workgroup::create();
+        let a = identity(&mut wg, None);
+        let b = identity(&mut wg, Some(a));
+        let c = identity(&mut wg, Some(b));
+        assert_eq!(wg.validate(), Ok(()));
+        wg.set_inputs(a, Some(b), None);
+        assert_eq!(
+            wg.validate(),
+            Err(vec![ValidationError::FeedbackLoop {
+                units: vec![a, b, c]
+            }])
+        );
+        assert!(matches!(wg.cycle(), Err(Error::InvalidGraph(_))));
+    }
+
+    #[test]
+    fn validate_rejects_dangling_ids() {
+        let mut other = Workgroup::create();
+        let foreign = identity(&mut other, None);
+        let mut wg = Workgroup::create();
+        let removed = identity(&mut wg, None);
+        let a = identity(&mut wg, Some(foreign));
+        let b = identity(&mut wg, Some(removed));
+        wg.remove_unit(removed);
+        assert_eq!(
+            wg.validate(),
+            Err(vec![
+                ValidationError::DanglingInput {
+                    unit: a,
+                    slot: Slot::X,
+                    input: foreign,
+                },
+                ValidationError::DanglingInput {
+                    unit: b,
+                    slot: Slot::X,
+                    input: removed,
+                },
+            ])
+        );
+        // (and nothing gets run)
+        assert!(matches!(wg.cycle(), Err(Error::InvalidGraph(_))));
+        assert_eq!(wg.get_unit(a).z, Term::Empty);
+    }
+
+    #[test]
+    fn collect_garbage_drops_unreachable_units() {
+        let mut wg = Workgroup::create();
+        let a = identity(&mut wg, None);
+        let b = identity(&mut wg, Some(a));
+        // (off to the side, and downstream of a root without being upstream of one)
+        let stray = identity(&mut wg, None);
+        let after = identity(&mut wg, Some(b));
+        let root = wg.add_arith(Arith::add(), a, b);
+        assert_eq!(wg.collect_garbage(&[root]), 2);
+        assert!(wg.try_get_unit(stray).is_none());
+        assert!(wg.try_get_unit(after).is_none());
+        assert_eq!(wg.schedule().collect::<Vec<_>>(), vec![a, b, root]);
+        assert_eq!(wg.validate(), Ok(()));
+        // nothing left to drop
+        assert_eq!(wg.collect_garbage(&[root]), 0);
+        wg.cycle().unwrap();
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip() {
+        use crate::unit::cfrac::{CFracSource, consts};
+
+        let mut wg = Workgroup::create();
+        let half = wg.add_from_cfrac(FromCFrac::simple(vec![0, 2]));
+        let exp = wg.add_exp_taylor(ExpTaylor::create(), half);
+        let pi = wg.add_from_cfrac(consts::pi());
+        let log = wg.add_log2(Log2::create(), pi);
+        let run = |wg: &mut Workgroup, exp: UnitId, log: UnitId| {
+            (0..100)
+                .map(|_| (wg.next_term(exp).unwrap(), wg.next_term(log).unwrap()))
+                .collect::<Vec<_>>()
+        };
+        let before = run(&mut wg, exp, log);
+        assert!(
+            before
+                .iter()
+                .any(|&(exp, log)| exp != Term::Empty && log != Term::Empty)
+        );
+        // (so the big coefficients get a look in too)
+        assert!(wg.units.values().any(|unit| unit.inner.max_bits() > 64));
+        let json = serde_json::to_string(&wg).unwrap();
+        let mut restored: Workgroup = serde_json::from_str(&json).unwrap();
+        // a fresh tag, so the original's ids don't work on it until they're adopted
+        assert!(!restored.owns(exp));
+        assert!(restored.adopt(exp).is_some_and(|id| restored.owns(id)));
+        let foreign = identity(&mut Workgroup::create(), None);
+        assert_eq!(restored.adopt(foreign), None);
+        assert_eq!(restored.validate(), Ok(()));
+        assert_eq!(restored.total_bits(), wg.total_bits());
+        let (restored_exp, restored_log) =
+            (restored.adopt(exp).unwrap(), restored.adopt(log).unwrap());
+        assert_eq!(
+            run(&mut restored, restored_exp, restored_log),
+            run(&mut wg, exp, log)
+        );
+
+        // closures can't be serialized, so a workgroup holding one won't serialize at all
+        // (rather than coming back without its source)
+        let mut wg = Workgroup::create();
+        wg.add_clogs(CLogs::from_fn(|| Term::Ord));
+        assert!(serde_json::to_string(&wg).is_err());
+        let mut wg = Workgroup::create();
+        let ones = CFracSource::boxed(std::iter::repeat((1, 1, 1, 0)));
+        wg.add_from_cfrac(FromCFrac::create(
+            [1.into(), 0.into(), 0.into(), 1.into()],
+            ones,
+        ));
+        assert!(serde_json::to_string(&wg).is_err());
+    }
+}
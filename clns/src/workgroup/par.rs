@@ -0,0 +1,78 @@
+use std::collections::BTreeMap;
+
+use rayon::prelude::*;
+
+use crate::{
+    Error,
+    workgroup::{UnitConcrete, Workgroup},
+};
+
+// within a phase every unit only looks at z values from before the phase
+// so the units can all be updated at once
+// (worth it once there are a lot of units doing real work, e.g. hundreds of ariths;
+// for a handful of units the thread pool overhead wins)
+impl Workgroup {
+    // same as cycle, but spread across rayon's thread pool
+    // (gives exactly the same results as cycle)
+    pub fn par_cycle(&mut self) -> Result<(), Error> {
+        self.ensure_valid()?;
+        let (phase, track) = (self.current_phase, self.track_metrics);
+        let inputs = self.phase_inputs()?;
+        // (pair every unit up with its input, in schedule order)
+        let mut units = self
+            .units
+            .iter_mut()
+            .map(|(index, unit)| (*index, unit))
+            .collect::<BTreeMap<u32, &mut UnitConcrete>>();
+        let steps = inputs
+            .into_iter()
+            .filter_map(|(index, input)| Some((units.remove(&index)?, input)))
+            .collect::<Vec<_>>();
+        steps
+            .into_par_iter()
+            .for_each(|(unit, input)| unit.step(phase, input, track));
+        self.current_phase = self.current_phase.next();
+        self.inner_failures()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        Term,
+        unit::{arith::Arith, cfrac::consts},
+        workgroup::UnitId,
+    };
+
+    fn pi_plus_e() -> (Workgroup, UnitId) {
+        let mut wg = Workgroup::create();
+        let pi = wg.add_from_cfrac(consts::pi());
+        let e = wg.add_from_cfrac(consts::e());
+        let out = wg.add_arith(Arith::add(), pi, e);
+        (wg, out)
+    }
+
+    #[test]
+    fn matches_cycle() {
+        let (mut seq, out) = pi_plus_e();
+        let mut par = seq.clone();
+        for _ in 0..300 {
+            seq.cycle().unwrap();
+            par.par_cycle().unwrap();
+            assert_eq!(seq.get_unit(out).z, par.get_unit(out).z);
+        }
+        assert_ne!(seq.get_unit(out).z, Term::Undefined);
+    }
+
+    #[test]
+    fn foreign_input_is_an_error() {
+        let (mut wg, out) = pi_plus_e();
+        let (_, foreign) = pi_plus_e();
+        // (egest first, so the graph's already been validated when we rewire by hand)
+        wg.par_cycle().unwrap();
+        wg.get_unit_mut(out).x = Some(foreign);
+        assert_eq!(wg.par_cycle(), Err(Error::UnknownUnit(foreign)));
+        assert_eq!(wg.cycle(), Err(Error::UnknownUnit(foreign)));
+    }
+}
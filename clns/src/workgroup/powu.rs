@@ -31,11 +31,7 @@ pub fn pow2(wg: &mut Workgroup, x: UnitId, d: u32) -> Vec<UnitId> {
     let mut items = vec![x];
     for _ in 0..d {
         let last = items.last().unwrap();
-        items.push(wg.add_arith(
-            Arith::create([1, 0, 0, 0, 0, 0, 0, 1].map(|i| i.into())),
-            *last,
-            *last,
-        ));
+        items.push(wg.add_arith(Arith::mul(), *last, *last));
     }
     items
 }
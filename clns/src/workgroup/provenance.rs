@@ -0,0 +1,184 @@
+use crate::{
+    Error, Term,
+    unit::{UndefinedReason, Unit},
+    workgroup::{Slot, UnitConcrete, UnitId, Workgroup},
+};
+
+// undefined poisons everything downstream of it
+// so by the time it reaches the output, every unit in between is egesting it too
+// each unit remembers when it first egested undefined and why, so we can walk back to the culprit
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Provenance {
+    // how many times the unit has egested (ø included)
+    pub egests: u64,
+    // the first slot an undefined came in through
+    pub undefined_input: Option<Slot>,
+    pub undefined: Option<Undefined>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Undefined {
+    pub reason: UndefinedReason,
+    // which egest it was (0 is the first)
+    pub egest: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UndefinedOrigin {
+    // the unit that went undefined without being handed an undefined input
+    // (or whose undefined input was hand-fed, in which case reason is Input)
+    pub unit: UnitId,
+    pub reason: UndefinedReason,
+    pub egest: u64,
+    // from the unit we asked about to the origin, both included
+    pub path: Vec<UnitId>,
+}
+
+impl UnitConcrete {
+    pub(super) fn record_ingest(&mut self, slot: Slot, term: Term) {
+        if term == Term::Undefined && self.provenance.undefined_input.is_none() {
+            self.provenance.undefined_input = Some(slot);
+        }
+    }
+
+    pub(super) fn record_egest(&mut self) {
+        if self.z == Term::Undefined && self.provenance.undefined.is_none() {
+            let reason = match self.provenance.undefined_input {
+                Some(slot) => UndefinedReason::Input(slot),
+                None => self.inner.undefined_reason(),
+            };
+            self.provenance.undefined = Some(Undefined {
+                reason,
+                egest: self.provenance.egests,
+            });
+        }
+        self.provenance.egests += 1;
+    }
+}
+
+impl Workgroup {
+    // where the undefined coming out of a unit started
+    // Ok(None) if the unit has never egested undefined
+    pub fn undefined_origin(&self, id: UnitId) -> Result<Option<UndefinedOrigin>, Error> {
+        let mut unit = self.try_get_unit(id).ok_or(Error::UnknownUnit(id))?;
+        let Some(mut undefined) = unit.provenance.undefined else {
+            return Ok(None);
+        };
+        let mut at = id;
+        let mut path = vec![id];
+        // follow undefined inputs upstream until we hit one that came up with it on its own
+        while let UndefinedReason::Input(slot) = undefined.reason {
+            let input = match slot {
+                Slot::X => unit.x,
+                Slot::Y => unit.y,
+            };
+            // (hand-fed, so the trail goes cold here)
+            let Some(input) = input else { break };
+            let Some(upstream) = self.try_get_unit(input) else {
+                break;
+            };
+            let Some(upstream_undefined) = upstream.provenance.undefined else {
+                break;
+            };
+            // (an unvalidated graph might have a loop in it)
+            if path.contains(&input) {
+                break;
+            }
+            unit = upstream;
+            undefined = upstream_undefined;
+            at = input;
+            path.push(input);
+        }
+        Ok(Some(UndefinedOrigin {
+            unit: at,
+            reason: undefined.reason,
+            egest: undefined.egest,
+            path,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::unit::{arith::Arith, cfrac::FromCFrac, int::Modulo, lft::Lft, sqrt::Sqrt};
+
+    // cycles until the unit egests undefined
+    fn run_until_undefined(wg: &mut Workgroup, id: UnitId) {
+        for _ in 0..200 {
+            if wg.next_term(id).unwrap() == Term::Undefined {
+                return;
+            }
+        }
+        panic!("{:?} never went undefined", id);
+    }
+
+    #[test]
+    fn records_the_first_undefined() {
+        let mut wg = Workgroup::create();
+        let one = wg.add_from_cfrac(FromCFrac::simple(vec![1]));
+        let sum = wg.add_arith(Arith::add(), one, one);
+        let unit = wg.get_unit_mut(sum);
+        unit.ingest(Slot::X, Term::Ord, false);
+        unit.egest(false);
+        unit.ingest(Slot::Y, Term::Undefined, false);
+        unit.ingest(Slot::X, Term::Undefined, false);
+        unit.egest(false);
+        unit.egest(false);
+        assert_eq!(unit.provenance.egests, 3);
+        assert_eq!(unit.provenance.undefined_input, Some(Slot::Y));
+        assert_eq!(
+            unit.provenance.undefined,
+            Some(Undefined {
+                reason: UndefinedReason::Input(Slot::Y),
+                egest: 1,
+            })
+        );
+    }
+
+    #[test]
+    fn walks_back_to_sqrt_of_negative() {
+        let mut wg = Workgroup::create();
+        let one = wg.add_from_cfrac(FromCFrac::simple(vec![1]));
+        let two = wg.add_from_cfrac(FromCFrac::simple(vec![2]));
+        let diff = wg.add_arith(Arith::sub(), one, two);
+        let root = wg.add_sqrt(Sqrt::create(), diff);
+        let out = wg.add_lft(Lft::affine(2, 1), Some(root));
+        run_until_undefined(&mut wg, out);
+        let origin = wg.undefined_origin(out).unwrap().unwrap();
+        assert_eq!(origin.unit, root);
+        assert_eq!(origin.reason, UndefinedReason::SqrtOfNegative);
+        assert_eq!(origin.path, vec![out, root]);
+        // (and the sqrt itself is where it stops)
+        assert_eq!(wg.undefined_origin(root).unwrap().unwrap().path, vec![root]);
+        assert_eq!(wg.undefined_origin(diff).unwrap(), None);
+    }
+
+    #[test]
+    fn walks_back_to_modulo_by_zero() {
+        let mut wg = Workgroup::create();
+        let three = wg.add_from_cfrac(FromCFrac::simple(vec![3]));
+        let zero = wg.add_from_cfrac(FromCFrac::simple(vec![0]));
+        let rem = wg.add_modulo(Modulo::create(), three, zero);
+        let root = wg.add_sqrt(Sqrt::create(), rem);
+        let out = wg.add_arith(Arith::add(), root, three);
+        run_until_undefined(&mut wg, out);
+        let origin = wg.undefined_origin(out).unwrap().unwrap();
+        assert_eq!(origin.unit, rem);
+        assert_eq!(origin.reason, UndefinedReason::BadModulo);
+        assert_eq!(origin.path, vec![out, root, rem]);
+        // the sqrt only went undefined because its input did
+        let root = wg.get_unit(root);
+        assert_eq!(
+            root.provenance.undefined.map(|undefined| undefined.reason),
+            Some(UndefinedReason::Input(Slot::X))
+        );
+        assert_eq!(
+            root.inner.undefined_reason(),
+            UndefinedReason::Input(Slot::X)
+        );
+    }
+}
@@ -0,0 +1,311 @@
+use std::{collections::VecDeque, time::Instant};
+
+use crate::{
+    Term,
+    workgroup::{Slot, UnitId, Workgroup},
+};
+
+// the pull model (what i originally reached for, see mod.rs)
+// instead of every unit ingesting and egesting every phase
+// we ask the output for a term, and only when it's stuck (egests ø)
+// does it ask its inputs for terms, recursively
+// so units that the output doesn't currently need don't do any work
+//
+// units can have several readers (shared subexpressions, the outside world)
+// so each unit keeps what it's egested until every reader has read it, and each reader keeps its place
+//
+// every unit gets fed between every two egests, same as in push mode
+// (units that wrap workgroups need that to stay in lockstep,
+// and arith and sqrt asked twice on the same information just speculate, then retract with -)
+// so a unit sees the same terms as it would in push mode, minus the ø:
+// readers never see ø, only real terms get recorded
+// which means the terms out aren't quite the same as push mode's (the value is)
+// and the output only shows ø when the fuel or the deadline runs out
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub(super) struct Tap {
+    // the terms some reader hasn't read yet
+    terms: VecDeque<Term>,
+    // how many terms came before terms[0] (dropped once every reader had read them)
+    dropped: usize,
+    // (reader, index of the next term it'll read, counting dropped terms)
+    readers: Vec<(Reader, usize)>,
+    egested: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+enum Reader {
+    // whoever's calling next_term
+    Outside,
+    Unit(u32, Slot),
+}
+
+impl Tap {
+    // (a reader that turns up after terms were dropped starts from the oldest one left)
+    fn position(&mut self, reader: Reader) -> &mut usize {
+        let i = match self.readers.iter().position(|(r, _)| *r == reader) {
+            Some(i) => i,
+            None => {
+                self.readers.push((reader, self.dropped));
+                self.readers.len() - 1
+            }
+        };
+        &mut self.readers[i].1
+    }
+
+    fn end(&self) -> usize {
+        self.dropped + self.terms.len()
+    }
+
+    // drops the terms every reader's past (all of them, if nobody's reading)
+    fn trim(&mut self) {
+        let read = self
+            .readers
+            .iter()
+            .map(|(_, at)| *at)
+            .min()
+            .unwrap_or(self.end());
+        if read > self.dropped {
+            self.terms.drain(..read - self.dropped);
+            self.dropped = read;
+        }
+    }
+}
+
+// how much a single pull gets to do
+struct Fuel {
+    // feeds left
+    left: u32,
+    deadline: Option<Instant>,
+    expired: bool,
+}
+
+impl Fuel {
+    // false (and nothing burnt) if we're out
+    fn burn(&mut self) -> bool {
+        if self
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            self.expired = true;
+        }
+        if self.left == 0 || self.expired {
+            return false;
+        }
+        self.left -= 1;
+        true
+    }
+}
+
+impl Workgroup {
+    // stops every tap waiting on the unit readers that `gone` picks out, given (producer, reader, slot)
+    // (units that were dropped or rewired elsewhere; otherwise trim never gets past
+    // where they stopped reading, and the terms pile up for good)
+    pub(super) fn forget_readers(&mut self, gone: impl Fn(u32, u32, Slot) -> bool) {
+        for (&producer, tap) in self.taps.iter_mut() {
+            tap.readers.retain(|(reader, _)| match *reader {
+                Reader::Unit(index, slot) => !gone(producer, index, slot),
+                Reader::Outside => true,
+            });
+            tap.trim();
+        }
+    }
+
+    // the next term out of the given unit
+    // fuel caps how many times units get to ask their inputs for more
+    // ø means we ran out (call again to keep going) or everything upstream has dried up
+    // None means the deadline passed first (checked every time a unit gets fed)
+    pub(super) fn pull(
+        &mut self,
+        id: UnitId,
+        fuel: u32,
+        deadline: Option<Instant>,
+    ) -> Option<Term> {
+        let mut fuel = Fuel {
+            left: fuel,
+            deadline,
+            expired: false,
+        };
+        let term = self.read(id.index, Reader::Outside, &mut fuel);
+        if term == Term::Empty && fuel.expired {
+            return None;
+        }
+        Some(term)
+    }
+
+    // the producer's tap, set up with every unit wired to it as a reader
+    // (so that nothing gets dropped before they've all read it, even if some haven't asked yet)
+    fn tap(&mut self, producer: u32) -> &mut Tap {
+        if !self.taps.contains_key(&producer) {
+            let mut tap = Tap::default();
+            for (&index, unit) in self.units.iter() {
+                for (slot, input) in [(Slot::X, unit.x), (Slot::Y, unit.y)] {
+                    if input.is_some_and(|input| self.owns(input) && input.index == producer) {
+                        tap.readers.push((Reader::Unit(index, slot), 0));
+                    }
+                }
+            }
+            self.taps.insert(producer, tap);
+        }
+        self.taps.get_mut(&producer).unwrap()
+    }
+
+    fn read(&mut self, producer: u32, reader: Reader, fuel: &mut Fuel) -> Term {
+        let tap = self.tap(producer);
+        let at = *tap.position(reader);
+        if at == tap.end() && self.produce(producer, fuel) == Term::Empty {
+            return Term::Empty;
+        }
+        let tap = self.tap(producer);
+        *tap.position(reader) += 1;
+        let term = tap.terms[at - tap.dropped];
+        tap.trim();
+        term
+    }
+
+    // egests the unit's next term, feeding it input terms until it has one
+    fn produce(&mut self, index: u32, fuel: &mut Fuel) -> Term {
+        // (the first egest doesn't need anything new)
+        let mut feed = self.tap(index).egested;
+        loop {
+            if feed {
+                if !fuel.burn() {
+                    return Term::Empty;
+                }
+                self.feed(index, fuel);
+            }
+            feed = true;
+            let track = self.track_metrics;
+            let term = self.units.get_mut(&index).unwrap().egest(track);
+            let tap = self.tap(index);
+            tap.egested = true;
+            if term != Term::Empty {
+                tap.terms.push_back(term);
+                return term;
+            }
+        }
+    }
+
+    // one term from each input (sources don't have any, they just need egesting again)
+    // ø gets passed along too, so lockstep units stay in step
+    fn feed(&mut self, index: u32, fuel: &mut Fuel) {
+        let unit = &self.units[&index];
+        let (x, y) = (unit.x, unit.y);
+        if let Some(x) = x {
+            let term = self.read(x.index, Reader::Unit(index, Slot::X), fuel);
+            let track = self.track_metrics;
+            self.units
+                .get_mut(&index)
+                .unwrap()
+                .ingest(Slot::X, term, track);
+        }
+        if let Some(y) = y {
+            let term = self.read(y.index, Reader::Unit(index, Slot::Y), fuel);
+            let track = self.track_metrics;
+            self.units
+                .get_mut(&index)
+                .unwrap()
+                .ingest(Slot::Y, term, track);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{
+        unit::{
+            arith::Arith,
+            cfrac::{FromCFrac, consts},
+            cmp::Compare,
+            sqrt::Sqrt,
+        },
+        workgroup::{
+            EvalMode,
+            eval::{Budget, Limit},
+        },
+    };
+
+    #[test]
+    fn taps_only_keep_unread_terms() {
+        let mut wg = Workgroup::create();
+        wg.set_mode(EvalMode::PULL);
+        let pi = wg.add_from_cfrac(consts::pi());
+        let square = wg.add_arith(Arith::mul(), pi, pi);
+        for _ in 0..500 {
+            wg.next_term(square).unwrap();
+        }
+        for tap in wg.taps.values() {
+            assert!(tap.terms.len() <= 2, "{:?}", tap.terms);
+            assert!(tap.dropped > 0);
+        }
+    }
+
+    #[test]
+    fn dropped_readers_dont_hold_terms() {
+        // (a reader that's been removed, or rewired away from pi, never reads again)
+        for rewire in [false, true] {
+            let mut wg = Workgroup::create();
+            wg.set_mode(EvalMode::PULL);
+            let pi = wg.add_from_cfrac(consts::pi());
+            let root = wg.add_sqrt(Sqrt::create(), pi);
+            let square = wg.add_arith(Arith::mul(), pi, pi);
+            for _ in 0..20 {
+                wg.next_term(root).unwrap();
+                wg.next_term(square).unwrap();
+            }
+            if rewire {
+                let two = wg.add_from_cfrac(FromCFrac::simple(vec![2]));
+                wg.set_inputs(square, Some(two), Some(two));
+            } else {
+                wg.remove_unit(square);
+            }
+            for _ in 0..500 {
+                wg.next_term(root).unwrap();
+            }
+            let tap = &wg.taps[&pi.index];
+            assert!(tap.terms.len() <= 2, "{:?}", tap.terms);
+        }
+    }
+
+    #[test]
+    fn same_value_as_push() {
+        let mut intervals = vec![];
+        for mode in [EvalMode::Push, EvalMode::PULL] {
+            let mut wg = Workgroup::create();
+            wg.set_mode(mode);
+            let pi = wg.add_from_cfrac(consts::pi());
+            let e = wg.add_from_cfrac(consts::e());
+            let out = wg.add_arith(Arith::div(), pi, e);
+            let evaluation = wg.evaluate(out, 300, &Budget::default()).unwrap();
+            let approx = evaluation.into_approximation();
+            // (pull is fed between egests like push, so it doesn't speculate any more than push)
+            let retractions = |terms: &[Term]| terms.iter().filter(|t| **t == Term::Neg).count();
+            intervals.push((approx.interval.unwrap(), retractions(&approx.terms)));
+        }
+        let [(push, push_retractions), (pull, pull_retractions)] = &intervals[..] else {
+            unreachable!()
+        };
+        assert!(push.intersects(pull));
+        assert!(pull_retractions <= push_retractions);
+    }
+
+    #[test]
+    fn deadline_stops_a_stuck_pull() {
+        let mut wg = Workgroup::create();
+        wg.set_mode(EvalMode::Pull { fuel: u32::MAX });
+        let two = wg.add_from_cfrac(FromCFrac::simple(vec![2]));
+        let a = wg.add_sqrt(Sqrt::create(), two);
+        let b = wg.add_sqrt(Sqrt::create(), two);
+        let cmp = wg.add_compare(Compare::create(), a, b);
+        let budget = Budget {
+            time: Some(Duration::from_millis(100)),
+            ..Budget::default()
+        };
+        let evaluation = wg.evaluate(cmp, 1, &budget).unwrap();
+        assert_eq!(evaluation.limit(), Some(Limit::Time));
+    }
+}
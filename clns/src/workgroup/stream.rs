@@ -0,0 +1,157 @@
+use std::iter::FusedIterator;
+
+use crate::{
+    Error, Term,
+    workgroup::{UnitId, Workgroup},
+};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StreamOptions {
+    // yield ø too, rather than skipping it
+    // (without this, an output that never settles makes next() spin forever)
+    pub include_stalls: bool,
+    // end after the first oo, since the value's exact by then and the rest is just more oo
+    pub stop_at_inf: bool,
+}
+
+// terms out of one unit, driving the workgroup however its mode says (see next_term)
+pub struct Stream<'a> {
+    wg: &'a mut Workgroup,
+    out: UnitId,
+    options: StreamOptions,
+    done: bool,
+}
+
+impl Workgroup {
+    // the graph's checked up front, so the stream itself can't fail
+    // (and it holds on to the workgroup, so nothing can break it while it's running)
+    pub fn stream(&mut self, out: UnitId, options: StreamOptions) -> Result<Stream<'_>, Error> {
+        if self.try_get_unit(out).is_none() {
+            return Err(Error::UnknownUnit(out));
+        }
+        self.ensure_valid()?;
+        Ok(Stream {
+            wg: self,
+            out,
+            options,
+            done: false,
+        })
+    }
+}
+
+impl Iterator for Stream<'_> {
+    type Item = Term;
+
+    fn next(&mut self) -> Option<Term> {
+        if self.done {
+            return None;
+        }
+        loop {
+            let term = self
+                .wg
+                .next_term(self.out)
+                .expect("the workgroup was validated when the stream was made");
+            if term == Term::Empty && !self.options.include_stalls {
+                continue;
+            }
+            if term == Term::Inf && self.options.stop_at_inf {
+                self.done = true;
+            }
+            return Some(term);
+        }
+    }
+}
+
+impl FusedIterator for Stream<'_> {}
+
+#[cfg(test)]
+mod tests {
+    use num_rational::BigRational;
+
+    use super::*;
+    use crate::{
+        interval::Interval,
+        unit::{CLogs, Unit, cfrac::FromCFrac, lft::Lft},
+    };
+
+    // where the terms put the value
+    fn value(terms: &[Term]) -> Option<Interval> {
+        let mut to_rat = Lft {
+            egest_enabled: false,
+            ..Lft::identity()
+        };
+        for term in terms {
+            to_rat.ingest_x(*term);
+        }
+        to_rat.interval()
+    }
+
+    #[test]
+    fn finite_rational() {
+        let mut wg = Workgroup::create();
+        let two = wg.add_from_cfrac(FromCFrac::simple(vec![2]));
+        let stop = StreamOptions {
+            stop_at_inf: true,
+            ..StreamOptions::default()
+        };
+        let terms = wg.stream(two, stop).unwrap().collect::<Vec<_>>();
+        // (the one oo it ends on is the only one)
+        assert_eq!(
+            terms.iter().position(|term| *term == Term::Inf),
+            Some(terms.len() - 1)
+        );
+        assert_eq!(
+            value(&terms),
+            Some(Interval::point(BigRational::from_integer(2.into())))
+        );
+        // otherwise it's oo forever after
+        let mut wg = Workgroup::create();
+        let two = wg.add_from_cfrac(FromCFrac::simple(vec![2]));
+        let more = wg
+            .stream(two, StreamOptions::default())
+            .unwrap()
+            .take(terms.len() + 20)
+            .collect::<Vec<_>>();
+        assert_eq!(more[..terms.len()], terms);
+        assert!(more[terms.len()..].iter().all(|term| *term == Term::Inf));
+    }
+
+    #[test]
+    fn stalls_only_if_asked_for() {
+        let terms = vec![
+            Term::Empty,
+            Term::Ord,
+            Term::Empty,
+            Term::Empty,
+            Term::Rec,
+            Term::Inf,
+        ];
+        let stream = |include_stalls| {
+            let mut wg = Workgroup::create();
+            let out = wg.add_clogs(CLogs::from_terms(terms.clone()));
+            let options = StreamOptions {
+                include_stalls,
+                stop_at_inf: true,
+            };
+            wg.stream(out, options).unwrap().collect::<Vec<_>>()
+        };
+        assert_eq!(stream(true), terms);
+        assert_eq!(stream(false), vec![Term::Ord, Term::Rec, Term::Inf]);
+    }
+
+    #[test]
+    fn nothing_after_the_end() {
+        let mut wg = Workgroup::create();
+        let out = wg.add_clogs(CLogs::from_terms(vec![Term::Ord, Term::Inf, Term::Ord]));
+        let options = StreamOptions {
+            stop_at_inf: true,
+            ..StreamOptions::default()
+        };
+        let mut stream = wg.stream(out, options).unwrap();
+        assert_eq!(stream.by_ref().count(), 2);
+        for _ in 0..10 {
+            assert_eq!(stream.next(), None);
+        }
+    }
+}
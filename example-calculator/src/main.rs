@@ -1,4 +1,7 @@
-use clns::{unit, workgroup};
+use clns::{
+    unit::{self, Unit},
+    workgroup,
+};
 
 mod parse;
 
@@ -13,31 +16,84 @@ fn main() {
     //     ClogTerms,
     // }
     struct Cfg {
+        // how many terms to ask the output for (ø included; this is the budget's cycle limit)
         egests: u32,
+        time: Option<std::time::Duration>,
+        bits: Option<u64>,
+        stall: Option<u64>,
+        mode: workgroup::EvalMode,
+        metrics: bool,
         // out_format: OutFormat,
     }
     let mut cfg = Cfg {
         egests: 100,
+        time: None,
+        bits: None,
+        stall: None,
+        mode: workgroup::EvalMode::Push,
+        metrics: false,
         // out_format: OutFormat::Rational,
     };
     while stdin.read_line(&mut buf).unwrap() > 0 {
         if buf.starts_with("cfg ") {
             let mut iter = buf.split(" ");
             let _ = iter.next().unwrap();
-            match iter.next().map(|s| s.trim()) {
-                Some(item) => match item {
-                    "egests" => {
-                        if let Some(Ok(item)) = iter.next().map(|i| i.trim().parse()) {
-                            cfg.egests = item;
-                            println!("Egests is now {}", cfg.egests);
-                        }
+            match (iter.next().map(|s| s.trim()), iter.next().map(|s| s.trim())) {
+                (Some("egests"), Some(item)) => {
+                    if let Ok(item) = item.parse() {
+                        cfg.egests = item;
+                        println!("Egests is now {}", cfg.egests);
                     }
-                    // "rational" => cfg.out_format = OutFormat::Rational,
-                    // "clog" => cfg.out_format = OutFormat::ClogTerms,
-                    // TODO more
-                    _ => {}
-                },
-                None => {}
+                }
+                (Some("time"), Some("off")) => {
+                    cfg.time = None;
+                    println!("Time limit is now off");
+                }
+                (Some("time"), Some(item)) => {
+                    if let Ok(ms) = item.parse() {
+                        cfg.time = Some(std::time::Duration::from_millis(ms));
+                        println!("Time limit is now {}ms", ms);
+                    }
+                }
+                (Some("bits"), Some("off")) => {
+                    cfg.bits = None;
+                    println!("Bit limit is now off");
+                }
+                (Some("bits"), Some(item)) => {
+                    if let Ok(item) = item.parse() {
+                        cfg.bits = Some(item);
+                        println!("Bit limit is now {}", item);
+                    }
+                }
+                (Some("stall"), Some("off")) => {
+                    cfg.stall = None;
+                    println!("Stall detection is now off");
+                }
+                (Some("stall"), Some(item)) => {
+                    if let Ok(item) = item.parse() {
+                        cfg.stall = Some(item);
+                        println!(
+                            "Stall detection now kicks in after {} terms closing in on one rational",
+                            item
+                        );
+                    }
+                }
+                (Some("mode"), Some("push")) => {
+                    cfg.mode = workgroup::EvalMode::Push;
+                    println!("Mode is now push");
+                }
+                (Some("mode"), Some("pull")) => {
+                    cfg.mode = workgroup::EvalMode::PULL;
+                    println!("Mode is now pull");
+                }
+                (Some("metrics"), Some(on @ ("on" | "off"))) => {
+                    cfg.metrics = on == "on";
+                    println!("Metrics are now {}", on);
+                }
+                // (Some("rational"), _) => cfg.out_format = OutFormat::Rational,
+                // (Some("clog"), _) => cfg.out_format = OutFormat::ClogTerms,
+                // TODO more
+                _ => {}
             }
             buf.clear();
             continue;
@@ -51,25 +107,57 @@ fn main() {
             }
         };
         println!("{:?}", dag);
-        let (mut wg, out, to_rat) = parse::stack_into_workgroup(dag);
-        let mut terms = vec![];
-        for _ in 0..cfg.egests * 3 {
-            wg.cycle();
-            if wg.current_phase == workgroup::WorkgroupPhase::IngestX {
-                // println!("[{:?}]", wg.get_unit(out).z);
-                terms.push(wg.get_unit(out).z);
-                if let unit::UnitUnion::Compare(cmp) = &wg.get_unit(out).inner {
-                    println!("{:?}", cmp.cmp());
-                }
+        let (mut wg, out) = match parse::stack_into_workgroup(*dag) {
+            Ok(built) => built,
+            Err(e) => {
+                eprintln!("Can't evaluate expression: {:?}", e);
+                buf.clear();
+                continue;
+            }
+        };
+        wg.set_mode(cfg.mode);
+        wg.set_track_metrics(cfg.metrics);
+        let budget = workgroup::eval::Budget {
+            cycles: Some(cfg.egests.into()),
+            time: cfg.time,
+            bits: cfg.bits,
+            stall: cfg.stall,
+        };
+        let evaluation = match wg.evaluate(out, usize::MAX, &budget) {
+            Ok(evaluation) => evaluation,
+            Err(e) => {
+                eprintln!("Evaluation failed: {}", e);
+                buf.clear();
+                continue;
             }
+        };
+        if let Some(limit) = evaluation.limit()
+            && limit != workgroup::eval::Limit::Cycles
+        {
+            println!("Stopped early ({:?} limit)", limit);
+        }
+        if let Some(stall) = evaluation.stall() {
+            // (roughly, going by bit lengths)
+            let bits = stall.within.denom().bits() as i64 - stall.within.numer().bits() as i64;
+            println!(
+                "Stalled for {} terms: value is within about 2^-{} of {}",
+                stall.cycles, bits, stall.near
+            );
         }
-        let unit::UnitUnion::Lft(lft) = &wg.get_unit(to_rat).inner else {
-            unreachable!()
+        let workgroup::eval::Approximation { terms, interval } = evaluation.into_approximation();
+        if let unit::UnitUnion::Compare(_) = &wg.get_unit(out).inner {
+            println!("{:?}", wg.comparison(out));
+        }
+        // (the interval's already worked out, but the rounding lives on lft)
+        let mut to_rat = unit::lft::Lft {
+            egest_enabled: false,
+            ..unit::lft::Lft::identity()
         };
-        for term in terms {
-            print!("{:?}", term)
+        for term in terms.iter() {
+            to_rat.ingest_x(*term);
         }
-        println!();
+        let lft = &to_rat;
+        println!("{}", clns::terms::Terms(terms));
         println!(
             "{:?} | {:?} | {:?} | {:?}",
             lft.trunc(),
@@ -77,7 +165,24 @@ fn main() {
             lft.ceil(),
             lft.round()
         );
-        println!("{:?}", lft.intervals());
+        match interval {
+            Some(interval) => println!("{}", interval),
+            None => println!("(no interval)"),
+        }
+        if let Ok(Some(origin)) = wg.undefined_origin(out) {
+            println!(
+                "Undefined since egest {} of {:?} {} ({:?})",
+                origin.egest,
+                origin.unit,
+                wg.get_unit(origin.unit).inner.kind(),
+                origin.reason
+            );
+        }
+        if cfg.metrics {
+            for (id, metrics) in wg.metrics().units {
+                println!("{:?} {}: {:?}", id, wg.get_unit(id).inner.kind(), metrics);
+            }
+        }
         buf.clear();
     }
 
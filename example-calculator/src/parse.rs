@@ -55,99 +55,72 @@ pub enum RollExprError {
     InvalidDecimal,
     InvalidName,
     EmptyStack,
+    // parses fine, but there's no unit for it yet
+    #[allow(unused)]
+    Unsupported(String),
+    #[allow(unused)]
+    InvalidGraph(clns::Error),
 }
 
+// repeated subexpressions share units (see workgroup::build)
 pub fn stack_into_workgroup(
-    node: Box<Node>,
-) -> (workgroup::Workgroup, workgroup::UnitId, workgroup::UnitId) {
-    let mut wg = workgroup::Workgroup::create();
-    fn inner(node: Box<Node>, wg: &mut workgroup::Workgroup) -> workgroup::UnitId {
-        match *node {
-            Node::Constant { kind: Constant::E } => wg.add_from_cfrac(unit::cfrac::consts::e()),
-            Node::Constant { kind: Constant::Pi } => wg.add_from_cfrac(unit::cfrac::consts::pi()),
+    node: Node,
+) -> Result<(workgroup::Workgroup, workgroup::UnitId), RollExprError> {
+    let mut builder = workgroup::build::Builder::create();
+    fn inner(
+        node: Node,
+        b: &mut workgroup::build::Builder,
+    ) -> Result<workgroup::UnitId, RollExprError> {
+        Ok(match node {
+            Node::Constant { kind: Constant::E } => b.e(),
+            Node::Constant { kind: Constant::Pi } => b.pi(),
             Node::Constant {
                 kind: Constant::Inf,
-            } => wg.add_clogs(unit::CLogs {
-                terms: Box::new(|| clns::Term::Inf),
-            }),
-            Node::CLog { items } => wg.add_clogs(unit::CLogs {
-                terms: Box::new({
-                    let mut iter = items.into_iter();
-                    move || iter.next().unwrap_or(clns::Term::Empty)
-                }),
-            }),
-            Node::CFrac { terms } => wg.add_from_cfrac(unit::cfrac::FromCFrac {
-                mat: [1, 0, 0, 1].map(|i| i.into()),
-                iter: Box::new(terms.into_iter().map(|t| (t, 1, 1, 1)))
-            }),
-            Node::Decimal { word, pow } => wg.add_lft(
-                unit::lft::Lft {
-                    mat: [
-                        word.clone().into(),
-                        word.into(),
-                        BigInt::from(10u32).pow(pow as u32),
-                        BigInt::from(10u32).pow(pow as u32),
-                    ],
-                    egest_enabled: true,
-                },
+            } => b.clog_repeat(clns::Term::Inf),
+            Node::CLog { items } => b.clog_terms(items),
+            Node::CFrac { terms } => b.simple_cfrac(terms),
+            Node::Decimal { word, pow } => b.lft(
+                unit::lft::Lft::from_rational(word, BigInt::from(10u32).pow(pow as u32)),
                 None,
             ),
             Node::OneChild(kind, node) => {
-                let child = inner(node, wg);
+                let child = inner(*node, b)?;
                 match kind {
-                    OneChild::Sqrt => wg.add_sqrt(unit::sqrt::Sqrt::create(), child),
-                    OneChild::Exp => wg.add_exp_taylor(
-                        {
-                            let exp = workgroup::exp::ExpTaylor::create();
-                            // exp.add_layer();
-                            exp
-                        },
-                        child,
-                    ),
-                    OneChild::Log2 => wg.add_log2(workgroup::log2::Log2::create(), child),
-                    _ => panic!(),
+                    OneChild::Sqrt => b.sqrt(child),
+                    // (use a plain add_exp_taylor with add_layer called on it to start with more taylor terms)
+                    OneChild::Exp => b.exp_taylor(child),
+                    OneChild::Log2 => b.log2(child),
+                    OneChild::Abs | OneChild::Ln => {
+                        return Err(RollExprError::Unsupported(format!("{:?}", kind)));
+                    }
                 }
             }
             Node::TwoChildren(kind, n1, n2) => {
-                let x = inner(n1, wg);
-                let y = inner(n2, wg);
+                let x = inner(*n1, b)?;
+                let y = inner(*n2, b)?;
                 match kind {
-                    TwoChildren::Add => wg.add_arith(
-                        unit::arith::Arith::create([0, 1, 1, 0, 0, 0, 0, 1].map(|i| i.into())),
-                        x,
-                        y,
-                    ),
-                    TwoChildren::Sub => wg.add_arith(
-                        unit::arith::Arith::create([0, 1, -1, 0, 0, 0, 0, 1].map(|i| i.into())),
-                        x,
-                        y,
-                    ),
-                    TwoChildren::Mul => wg.add_arith(
-                        unit::arith::Arith::create([1, 0, 0, 0, 0, 0, 0, 1].map(|i| i.into())),
-                        x,
-                        y,
-                    ),
-                    TwoChildren::Div => wg.add_arith(
-                        unit::arith::Arith::create([0, 1, 0, 0, 0, 0, 1, 0].map(|i| i.into())),
-                        x,
-                        y,
-                    ),
-                    TwoChildren::Mod => wg.add_modulo(unit::int::Modulo::create(), x, y),
-                    TwoChildren::Compare => wg.add_compare(unit::cmp::Compare::create(), x, y),
-                    _ => todo!(),
+                    TwoChildren::Add => b.arith(unit::arith::Arith::add(), x, y),
+                    TwoChildren::Sub => b.arith(unit::arith::Arith::sub(), x, y),
+                    TwoChildren::Mul => b.arith(unit::arith::Arith::mul(), x, y),
+                    TwoChildren::Div => b.arith(unit::arith::Arith::div(), x, y),
+                    TwoChildren::Mod => b.modulo(x, y),
+                    TwoChildren::Compare => b.compare(x, y),
+                    TwoChildren::Floor
+                    | TwoChildren::Ceil
+                    | TwoChildren::Round
+                    | TwoChildren::Pow
+                    | TwoChildren::Log => {
+                        return Err(RollExprError::Unsupported(format!("{:?}", kind)));
+                    }
                 }
             }
-        }
+        })
     }
-    let output = inner(node, &mut wg);
-    let to_rat = wg.add_lft(
-        unit::lft::Lft {
-            mat: [1.into(), 0.into(), 0.into(), 1.into()],
-            egest_enabled: false,
-        },
-        Some(output),
-    );
-    (wg, output, to_rat)
+    let output = inner(node, &mut builder)?;
+    let wg = builder
+        .try_into_workgroup()
+        .map_err(RollExprError::InvalidGraph)?;
+    Ok((wg, output))
 }
 
 // rolls a stack expression into a DAG
@@ -212,30 +185,20 @@ pub fn roll_stack_expression(expr: &str) -> Result<Box<Node>, RollExprError> {
                     return Err(RollExprError::InvalidToken(token.to_owned()));
                 }
                 // TODO RATIONAL INPUTS!!!!
-                if other.starts_with("f:") {
+                if let Some(rest) = other.strip_prefix("f:") {
                     let mut terms = vec![];
-                    for term in other[2..].split(",") {
+                    for term in rest.split(",") {
                         // TODO optional generalized cfrac inputs
                         if let Ok(t) = term.parse() {
                             terms.push(t);
                         }
                     }
                     stack.push(Box::new(Node::CFrac { terms }));
-                } else if other.starts_with("c") {
+                } else if let Some(rest) = other.strip_prefix("c") {
                     // clog terms
-                    let mut items = vec![];
-                    for c in other.chars() {
-                        match c {
-                            '1' => items.push(clns::Term::Ord),
-                            '0' => items.push(clns::Term::DRec),
-                            '/' => items.push(clns::Term::Rec),
-                            '-' => items.push(clns::Term::Neg),
-                            'ø' => items.push(clns::Term::Empty),
-                            '∞' => items.push(clns::Term::Inf),
-                            '!' => items.push(clns::Term::Undefined),
-                            _ => {}
-                        }
-                    }
+                    let clns::terms::Terms(items) = rest
+                        .parse()
+                        .map_err(|_| RollExprError::InvalidToken(token.to_owned()))?;
                     stack.push(Box::new(Node::CLog { items }))
                 } else if other.chars().all(|c| c.is_numeric() || c == '.') {
                     // decimal
@@ -243,15 +206,15 @@ pub fn roll_stack_expression(expr: &str) -> Result<Box<Node>, RollExprError> {
                     if points > 1 || other.chars().count() == points {
                         return Err(RollExprError::InvalidDecimal);
                     }
-                    let zero = !other.contains(|c| match c {
-                        '1' | '2' | '3' | '4' | '5' | '6' | '7' | '8' | '9' => true,
-                        _ => false,
+                    let zero = !other.contains(|c| {
+                        matches!(c, '1' | '2' | '3' | '4' | '5' | '6' | '7' | '8' | '9')
                     });
                     if !zero {
                         let pow = other.len()
                             - other.find('.').unwrap_or(other.len())
                             - other.contains(".") as usize;
-                        let pow = pow.try_into().expect("if your pow can't fit into a u16 then you need to reevaluate your life");
+                        // (if your pow can't fit into a u16 then you need to reevaluate your life)
+                        let pow = pow.try_into().map_err(|_| RollExprError::InvalidDecimal)?;
                         let word = other.chars().filter(|c| *c != '.').collect::<String>();
                         stack.push(Box::new(Node::Decimal {
                             word: BigUint::from_str(&word).unwrap(),